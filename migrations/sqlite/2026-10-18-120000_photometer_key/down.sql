@@ -0,0 +1,141 @@
+-- Back to one photometer per role and session: only the first one is kept,
+-- and no decoding statistics
+DROP VIEW IF EXISTS rounds_v;
+DROP VIEW IF EXISTS summary_v;
+
+CREATE TABLE samples_old
+(
+    tstamp          TIMESTAMP NOT NULL,
+    role            TEXT      NOT NULL,
+    session         TIMESTAMP,
+    freq            REAL,
+    seq             INTEGER,
+    temp_box        REAL,
+    PRIMARY KEY(role, tstamp)
+);
+INSERT OR IGNORE INTO samples_old(tstamp, role, session, freq, seq, temp_box)
+SELECT tstamp, role, session, freq, seq, temp_box FROM samples_t;
+DROP TABLE samples_t;
+ALTER TABLE samples_old RENAME TO samples_t;
+
+CREATE TABLE rounds_old
+(
+    session         TIMESTAMP NOT NULL,
+    round           INTEGER NOT NULL,
+    role            TEXT NOT NULL,
+    begin_tstamp    TIMESTAMP,
+    end_tstamp      TIMESTAMP,
+    central         TEXT,
+    freq            REAL,
+    stddev          REAL,
+    mag             REAL,
+    zp_fict         REAL,
+    zero_point      REAL,
+    nsamples        INTEGER,
+    duration        REAL,
+    PRIMARY KEY(session, role, round)
+);
+INSERT OR IGNORE INTO rounds_old(session, round, role, begin_tstamp, end_tstamp, central, freq,
+    stddev, mag, zp_fict, zero_point, nsamples, duration)
+SELECT session, round, role, begin_tstamp, end_tstamp, central, freq,
+    stddev, mag, zp_fict, zero_point, nsamples, duration FROM rounds_t;
+DROP TABLE rounds_t;
+ALTER TABLE rounds_old RENAME TO rounds_t;
+
+CREATE TABLE summary_old
+(
+    session           TIMESTAMP NOT NULL,
+    role              TEXT NOT NULL,
+    calibration       TEXT,
+    calversion        TEXT,
+    model             TEXT,
+    name              TEXT,
+    mac               TEXT,
+    firmware          TEXT,
+    sensor            TEXT,
+    prev_zp           REAL,
+    author            TEXT,
+    nrounds           INTEGER,
+    offset            REAL,
+    upd_flag          INTEGER,
+    zero_point        REAL,
+    zero_point_method TEXT,
+    freq              REAL,
+    freq_method       TEXT,
+    mag               REAL,
+    filter            TEXT,
+    plug              TEXT,
+    box               TEXT,
+    collector         TEXT,
+    comment           TEXT,
+    PRIMARY KEY(session, role)
+);
+INSERT OR IGNORE INTO summary_old(session, role, calibration, calversion, model, name, mac,
+    firmware, sensor, prev_zp, author, nrounds, offset, upd_flag, zero_point, zero_point_method,
+    freq, freq_method, mag, filter, plug, box, collector, comment)
+SELECT session, role, calibration, calversion, model, name, mac,
+    firmware, sensor, prev_zp, author, nrounds, offset, upd_flag, zero_point, zero_point_method,
+    freq, freq_method, mag, filter, plug, box, collector, comment FROM summary_t;
+DROP TABLE summary_t;
+ALTER TABLE summary_old RENAME TO summary_t;
+
+DROP TABLE IF EXISTS decoding_t;
+
+CREATE VIEW IF NOT EXISTS rounds_v
+AS SELECT
+    r.session,
+    r.round,
+    r.role,
+    r.begin_tstamp,
+    r.end_tstamp,
+    r.central,
+    r.freq,
+    r.stddev,
+    r.mag,
+    r.zp_fict,
+    r.zero_point,
+    r.nsamples,
+    r.duration,
+    s.model,
+    s.name,
+    s.mac,
+    s.nrounds,
+    s.upd_flag
+FROM rounds_t AS r
+JOIN summary_t AS s USING (session, role);
+
+CREATE VIEW IF NOT EXISTS summary_v
+AS SELECT
+    test_t.session,
+    test_t.role,
+    test_t.calibration,
+    test_t.calversion,
+    test_t.model,
+    test_t.name,
+    test_t.mac,
+    test_t.firmware,
+    test_t.sensor,
+    test_t.prev_zp,
+    test_t.author,
+    test_t.nrounds,
+    test_t.offset,
+    test_t.upd_flag,
+    ROUND(test_t.zero_point, 2) AS zero_point,
+    test_t.zero_point_method,
+    ROUND(test_t.freq,3)        AS test_freq,
+    test_t.freq_method          AS test_freq_method,
+    ROUND(test_t.mag, 2)        AS test_mag,
+    ROUND(ref_t.freq, 3)        AS ref_freq,
+    ref_t.freq_method           AS ref_freq_method,
+    ROUND(ref_t.mag, 2)         AS ref_mag,
+    ROUND(ref_t.mag - test_t.mag, 2) AS mag_diff,
+    ROUND(test_t.zero_point, 2) - test_t.offset as raw_zero_point,
+    test_t.filter,
+    test_t.plug,
+    test_t.box,
+    test_t.collector,
+    test_t.comment
+
+FROM summary_t AS ref_t
+JOIN summary_t AS test_t USING (session)
+WHERE test_t.role = 'test' AND ref_t.role = 'ref';
@@ -0,0 +1,189 @@
+-- Several test photometers, or the channels of a multi-channel one, are calibrated
+-- in a single session. Samples, rounds, summaries and decoding statistics are told
+-- apart by photometer name and channel, 0 for single channel photometers.
+-- Samples are also told apart by session, as a capture replayed into several
+-- calibrations yields the same sample timestamps every time.
+-- SQLite can't change a primary key, so the tables are rebuilt.
+
+DROP VIEW IF EXISTS rounds_v;
+DROP VIEW IF EXISTS summary_v;
+
+CREATE TABLE samples_new
+(
+    tstamp          TIMESTAMP NOT NULL,  -- sample timestamp
+    role            TEXT      NOT NULL,  -- either 'test' or 'ref'
+    name            TEXT      NOT NULL,  -- photometer name
+    channel         INTEGER   NOT NULL,  -- photometer channel, 0 for single channel photometers
+    session         TIMESTAMP NOT NULL,  -- calibration session identifier
+    freq            REAL,       -- measured frequency
+    seq             INTEGER,    -- sequence number for JSON based raw readings, NULL otherwise
+    temp_box        REAL,       -- Box temperature for JSON based raw readings, NULL otherwise
+
+    PRIMARY KEY(session, role, tstamp, name, channel)
+);
+
+INSERT INTO samples_new(tstamp, role, name, channel, session, freq, seq, temp_box)
+SELECT s.tstamp, s.role,
+    COALESCE((SELECT m.name FROM summary_t AS m WHERE m.session = s.session AND m.role = s.role LIMIT 1), ''),
+    0, COALESCE(s.session, s.tstamp), s.freq, s.seq, s.temp_box
+FROM samples_t AS s;
+
+DROP TABLE samples_t;
+ALTER TABLE samples_new RENAME TO samples_t;
+
+CREATE TABLE rounds_new
+(
+    session         TIMESTAMP NOT NULL,  -- calibration session identifier
+    round           INTEGER NOT NULL,    -- to link ref and test windows
+    role            TEXT NOT NULL,       -- either 'test' or 'ref'
+    name            TEXT NOT NULL,       -- photometer name
+    channel         INTEGER NOT NULL,    -- photometer channel, 0 for single channel photometers
+    begin_tstamp    TIMESTAMP,  -- calibration window start timestamp
+    end_tstamp      TIMESTAMP,  -- calibration window end timestamp
+    central         TEXT,       -- estimate of central tendency: either 'mean','median' or 'mode'
+    freq            REAL,       -- central frequency estimate
+    stddev          REAL,       -- Standard deviation for frequency central estimate
+    mag             REAL,       -- magnitiude corresponding to central frequency and summing ficticious zero point
+    zp_fict         REAL,       -- Ficticious ZP to estimate instrumental magnitudes (=20.50)
+    zero_point      REAL,       -- Estimated Zero Point for this round ('test' photometer round only, else NULL)
+    nsamples        INTEGER,    -- Number of samples for this round
+    duration        REAL,       -- Approximate duration, in seconds
+
+    PRIMARY KEY(session, role, round, name, channel)
+);
+
+INSERT INTO rounds_new(session, round, role, name, channel, begin_tstamp, end_tstamp, central,
+    freq, stddev, mag, zp_fict, zero_point, nsamples, duration)
+SELECT r.session, r.round, r.role,
+    COALESCE((SELECT m.name FROM summary_t AS m WHERE m.session = r.session AND m.role = r.role LIMIT 1), ''),
+    0, r.begin_tstamp, r.end_tstamp, r.central, r.freq, r.stddev, r.mag, r.zp_fict, r.zero_point,
+    r.nsamples, r.duration
+FROM rounds_t AS r;
+
+DROP TABLE rounds_t;
+ALTER TABLE rounds_new RENAME TO rounds_t;
+
+CREATE TABLE summary_new
+(
+    session           TIMESTAMP NOT NULL,  -- calibration session identifier
+    role              TEXT NOT NULL,       -- either 'test' or 'ref'
+    calibration       TEXT,       -- either 'MANUAL' or 'AUTO'
+    calversion        TEXT,       -- calibration software version
+    model             TEXT,  -- TESS model
+    name              TEXT NOT NULL,     -- TESS name
+    channel           INTEGER NOT NULL,  -- TESS channel, 0 for single channel photometers
+    mac               TEXT,  -- TESS MAC address
+    firmware          TEXT,  -- firmware revision
+    sensor            TEXT,  -- Sensor model (TSL237, S9705-01DT)
+    prev_zp           REAL,  -- previous ZP before calibration
+    author            TEXT,  -- who run the calibration
+    nrounds           INTEGER, -- Number of rounds passed
+    offset            REAL,  -- Additional offset that was summed to the computed zero_point
+    upd_flag          INTEGER, -- 1 => TESS-W ZP was updated, 0 => TESS-W ZP was not updated,
+    zero_point        REAL,  -- calibrated zero point
+    zero_point_method TEXT,  -- either the 'mode' or 'median' of the different rounds
+    freq              REAL,  -- final chosen frequency
+    freq_method       TEXT,  -- either the 'mode' or 'median' of the different rounds
+    mag               REAL,  -- final chosen magnitude uzing ficticious ZP
+    filter            TEXT,  -- Filter type (i.e. UV-IR/740)
+    plug              TEXT,  -- Plug type (i.e. USB-A)
+    box               TEXT,  -- Box model (i.e. FSH714)
+    collector         TEXT,  -- Collector model
+    comment           TEXT,  -- Additional comment for the callibration process
+    PRIMARY KEY(session, role, name, channel)
+);
+
+INSERT INTO summary_new(session, role, calibration, calversion, model, name, channel, mac,
+    firmware, sensor, prev_zp, author, nrounds, offset, upd_flag, zero_point, zero_point_method,
+    freq, freq_method, mag, filter, plug, box, collector, comment)
+SELECT session, role, calibration, calversion, model, COALESCE(name, ''), 0, mac,
+    firmware, sensor, prev_zp, author, nrounds, offset, upd_flag, zero_point, zero_point_method,
+    freq, freq_method, mag, filter, plug, box, collector, comment
+FROM summary_t;
+
+DROP TABLE summary_t;
+ALTER TABLE summary_new RENAME TO summary_t;
+
+-- Readings decoded and rejected per photometer in a calibration session,
+-- to tell a noisy link from a broken photometer
+CREATE TABLE IF NOT EXISTS decoding_t
+(
+    session         TIMESTAMP NOT NULL,  -- calibration session identifier
+    role            TEXT NOT NULL,       -- either 'test' or 'ref'
+    name            TEXT NOT NULL,       -- photometer name
+    channel         INTEGER NOT NULL,    -- photometer channel, 0 for single channel photometers
+    accepted        INTEGER,    -- lines decoded into samples
+    malformed       INTEGER,    -- lines not in the expected format
+    duplicate       INTEGER,    -- readings sent again
+    bootstrap       INTEGER,    -- first readings, only used to filter out duplicates
+    out_of_range    INTEGER,    -- readings with impossible values
+    unknown_format  INTEGER,    -- lines no decoder recognizes
+    out_of_order    INTEGER,    -- readings sent before the previous one
+    lost            INTEGER,    -- readings never received
+    reboots         INTEGER,    -- photometer restarted counting
+    format          TEXT,       -- payload format detected by the reading task: 'JSON', 'Cristogg' or 'TESS4C'
+
+    PRIMARY KEY(session, role, name, channel)
+);
+
+CREATE VIEW IF NOT EXISTS rounds_v
+AS SELECT
+    r.session,
+    r.round,
+    r.role,
+    r.begin_tstamp,
+    r.end_tstamp,
+    r.central,
+    r.freq,
+    r.stddev,
+    r.mag,
+    r.zp_fict,
+    r.zero_point,
+    r.nsamples,
+    r.duration,
+    s.model,
+    s.name,
+    s.channel,
+    s.mac,
+    s.nrounds,
+    s.upd_flag
+FROM rounds_t AS r
+JOIN summary_t AS s USING (session, role, name, channel);
+
+-- One row per test photometer and channel, next to the reference photometer of the session
+CREATE VIEW IF NOT EXISTS summary_v
+AS SELECT
+    test_t.session,
+    test_t.role,
+    test_t.calibration,
+    test_t.calversion,
+    test_t.model,
+    test_t.name,
+    test_t.channel,
+    test_t.mac,
+    test_t.firmware,
+    test_t.sensor,
+    test_t.prev_zp,
+    test_t.author,
+    test_t.nrounds,
+    test_t.offset,
+    test_t.upd_flag,
+    ROUND(test_t.zero_point, 2) AS zero_point,
+    test_t.zero_point_method,
+    ROUND(test_t.freq,3)        AS test_freq,
+    test_t.freq_method          AS test_freq_method,
+    ROUND(test_t.mag, 2)        AS test_mag,
+    ROUND(ref_t.freq, 3)        AS ref_freq,
+    ref_t.freq_method           AS ref_freq_method,
+    ROUND(ref_t.mag, 2)         AS ref_mag,
+    ROUND(ref_t.mag - test_t.mag, 2) AS mag_diff,
+    ROUND(test_t.zero_point, 2) - test_t.offset as raw_zero_point,
+    test_t.filter,
+    test_t.plug,
+    test_t.box,
+    test_t.collector,
+    test_t.comment
+
+FROM summary_t AS ref_t
+JOIN summary_t AS test_t USING (session)
+WHERE test_t.role = 'test' AND ref_t.role = 'ref';
@@ -1,31 +1,34 @@
+use clap::builder::PossibleValue;
 use clap::ArgAction::{Append, Count};
 use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::path::PathBuf;
+use std::sync::OnceLock;
+use zptess::photometer::transport::Endpoint;
+use zptess::photometer::DEFAULT_SILENCE_SECS;
 
 pub fn parse() -> Cli {
     Cli::parse()
 }
 
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Debug)]
-#[clap(rename_all = "kebab-case")]
-pub enum Model {
-    /// TESS WiFi model
-    TessW,
+// Photometer models come from the profile table
+#[derive(Copy, Clone, Debug)]
+pub struct Model(zptess::Model);
 
-    /// TESS Portable model
-    TessP,
+impl ValueEnum for Model {
+    fn value_variants<'a>() -> &'a [Self] {
+        static MODELS: OnceLock<Vec<Model>> = OnceLock::new();
+        MODELS.get_or_init(|| zptess::Model::all().map(Model).collect())
+    }
 
-    /// TESS Auto Scan model
-    TAS,
+    fn to_possible_value(&self) -> Option<PossibleValue> {
+        let profile = self.0.profile();
+        Some(PossibleValue::new(profile.cli).help(profile.about))
+    }
 }
 
 impl Model {
     pub fn map_model(&self) -> zptess::Model {
-        match self {
-            Model::TessW => zptess::Model::Tessw,
-            Model::TAS => zptess::Model::Tas,
-            Model::TessP => zptess::Model::Tessp,
-        }
+        self.0
     }
 }
 
@@ -101,6 +104,26 @@ pub enum Commands {
         /// Specific operation
         #[command(flatten)]
         operation: Operation,
+
+        /// Pause instead of aborting the calibration when a photometer goes silent
+        #[arg(long)]
+        pause_on_silence: bool,
+
+        /// Number of test photometers calibrated at once, told apart by their name
+        #[arg(long, value_name = "N", default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+        tests: u16,
+
+        /// Photometer endpoints
+        #[command(flatten)]
+        endpoints: Endpoints,
+
+        /// Capture raw photometer traffic to a JSON Lines file
+        #[arg(long, value_name = "FILE")]
+        capture: Option<PathBuf>,
+
+        /// Seconds without readings before a photometer is reported as silent
+        #[arg(long, value_name = "SECS", default_value_t = DEFAULT_SILENCE_SECS)]
+        silence: u64,
     },
 
     // Continuosly read photometer(s)
@@ -112,6 +135,22 @@ pub enum Commands {
         /// Read photometer
         #[arg(short, long, value_name = "ROLE", value_enum)]
         role: Role,
+
+        /// Save all samples to database
+        #[arg(short, long)]
+        save: bool,
+
+        /// Photometer endpoints
+        #[command(flatten)]
+        endpoints: Endpoints,
+
+        /// Capture raw photometer traffic to a JSON Lines file
+        #[arg(long, value_name = "FILE")]
+        capture: Option<PathBuf>,
+
+        /// Seconds without readings before a photometer is reported as silent
+        #[arg(long, value_name = "SECS", default_value_t = DEFAULT_SILENCE_SECS)]
+        silence: u64,
     },
 
     // Updates Zero point directly
@@ -133,7 +172,7 @@ pub struct Operation {
     #[arg(short, long)]
     pub dry_run: bool,
 
-    /// Calibrate and update zero point
+    /// Calibrate and update zero point (not for TESS-P, TAS and TESS4C yet)
     #[arg(short, long)]
     pub update: bool,
 
@@ -141,3 +180,14 @@ pub struct Operation {
     #[arg(short, long)]
     pub test: bool,
 }
+
+#[derive(Args, Debug)]
+pub struct Endpoints {
+    /// Reference photometer endpoint, i.e. serial:/dev/ttyUSB0:9600 (overrides database)
+    #[arg(long, value_name = "ENDPOINT")]
+    pub ref_endpoint: Option<Endpoint>,
+
+    /// Test photometer endpoint, i.e. udp:0.0.0.0:2255[,from=<ip>][,name=<name>] (overrides database)
+    #[arg(long, value_name = "ENDPOINT")]
+    pub test_endpoint: Option<Endpoint>,
+}
@@ -28,7 +28,8 @@ pub struct SummaryView {
     pub calibration: Option<String>,
     pub calversion: Option<String>,
     pub model: Option<String>,
-    pub name: Option<String>,
+    pub name: String,
+    pub channel: i32,
     pub mac: Option<String>,
     pub firmware: Option<String>,
     pub sensor: Option<String>,
@@ -72,8 +73,95 @@ pub struct RoundsView {
     pub nsamples: Option<i32>,
     pub duration: Option<f32>,
     pub model: Option<String>,
-    pub name: Option<String>,
+    pub name: String,
+    pub channel: i32,
     pub mac: Option<String>,
     pub nrounds: Option<i32>,
     pub upd_flag: Option<i32>,
 }
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::database::schema::rounds_t)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Round {
+    pub session: String,
+    pub round: i32,
+    pub role: String,
+    pub name: String,
+    pub channel: i32,
+    pub begin_tstamp: Option<String>,
+    pub end_tstamp: Option<String>,
+    pub central: Option<String>,
+    pub freq: Option<f32>,
+    pub stddev: Option<f32>,
+    pub mag: Option<f32>,
+    pub zp_fict: Option<f32>,
+    pub zero_point: Option<f32>,
+    pub nsamples: Option<i32>,
+    pub duration: Option<f32>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::database::schema::decoding_t)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Decoding {
+    pub session: String,
+    pub role: String,
+    pub name: String,
+    pub channel: i32,
+    pub accepted: Option<i32>,
+    pub malformed: Option<i32>,
+    pub duplicate: Option<i32>,
+    pub bootstrap: Option<i32>,
+    pub out_of_range: Option<i32>,
+    pub unknown_format: Option<i32>,
+    pub out_of_order: Option<i32>,
+    pub lost: Option<i32>,
+    pub reboots: Option<i32>,
+    pub format: Option<String>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::database::schema::summary_t)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Summary {
+    pub session: String,
+    pub role: String,
+    pub calibration: Option<String>,
+    pub calversion: Option<String>,
+    pub model: Option<String>,
+    pub name: String,
+    pub channel: i32,
+    pub mac: Option<String>,
+    pub firmware: Option<String>,
+    pub sensor: Option<String>,
+    pub prev_zp: Option<f32>,
+    pub author: Option<String>,
+    pub nrounds: Option<i32>,
+    pub offset: Option<f32>,
+    pub upd_flag: Option<i32>,
+    pub zero_point: Option<f32>,
+    pub zero_point_method: Option<String>,
+    pub freq: Option<f32>,
+    pub freq_method: Option<String>,
+    pub mag: Option<f32>,
+    pub filter: Option<String>,
+    pub plug: Option<String>,
+    pub box_: Option<String>,
+    pub collector: Option<String>,
+    pub comment: Option<String>,
+}
+
+#[derive(Insertable, Debug)]
+#[diesel(table_name = crate::database::schema::samples_t)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Sample {
+    pub tstamp: String,
+    pub role: String,
+    pub name: String,
+    pub channel: i32,
+    pub session: String,
+    pub freq: Option<f32>,
+    pub seq: Option<i32>,
+    pub temp_box: Option<f32>,
+}
@@ -19,10 +19,31 @@ diesel::table! {
 }
 
 diesel::table! {
-    rounds_t (session, round, role) {
-        session -> Nullable<Timestamp>,
-        round -> Nullable<Integer>,
-        role -> Nullable<Text>,
+    decoding_t (session, role, name, channel) {
+        session -> Timestamp,
+        role -> Text,
+        name -> Text,
+        channel -> Integer,
+        accepted -> Nullable<Integer>,
+        malformed -> Nullable<Integer>,
+        duplicate -> Nullable<Integer>,
+        bootstrap -> Nullable<Integer>,
+        out_of_range -> Nullable<Integer>,
+        unknown_format -> Nullable<Integer>,
+        out_of_order -> Nullable<Integer>,
+        lost -> Nullable<Integer>,
+        reboots -> Nullable<Integer>,
+        format -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    rounds_t (session, role, round, name, channel) {
+        session -> Timestamp,
+        round -> Integer,
+        role -> Text,
+        name -> Text,
+        channel -> Integer,
         begin_tstamp -> Nullable<Timestamp>,
         end_tstamp -> Nullable<Timestamp>,
         central -> Nullable<Text>,
@@ -37,10 +58,12 @@ diesel::table! {
 }
 
 diesel::table! {
-    samples_t (tstamp, role) {
-        tstamp -> Nullable<Timestamp>,
-        role -> Nullable<Text>,
-        session -> Nullable<Timestamp>,
+    samples_t (session, role, tstamp, name, channel) {
+        tstamp -> Timestamp,
+        role -> Text,
+        name -> Text,
+        channel -> Integer,
+        session -> Timestamp,
         freq -> Nullable<Float>,
         seq -> Nullable<Integer>,
         temp_box -> Nullable<Float>,
@@ -48,13 +71,17 @@ diesel::table! {
 }
 
 diesel::table! {
-    summary_t (session, role) {
-        session -> Nullable<Timestamp>,
-        role -> Nullable<Text>,
+    summary_t (session, role, name, channel) {
+        session -> Timestamp,
+        role -> Text,
+        calibration -> Nullable<Text>,
+        calversion -> Nullable<Text>,
         model -> Nullable<Text>,
-        name -> Nullable<Text>,
+        name -> Text,
+        channel -> Integer,
         mac -> Nullable<Text>,
         firmware -> Nullable<Text>,
+        sensor -> Nullable<Text>,
         prev_zp -> Nullable<Float>,
         author -> Nullable<Text>,
         nrounds -> Nullable<Integer>,
@@ -65,21 +92,19 @@ diesel::table! {
         freq -> Nullable<Float>,
         freq_method -> Nullable<Text>,
         mag -> Nullable<Float>,
-        calibration -> Nullable<Text>,
         filter -> Nullable<Text>,
         plug -> Nullable<Text>,
         #[sql_name = "box"]
         box_ -> Nullable<Text>,
         collector -> Nullable<Text>,
         comment -> Nullable<Text>,
-        sensor -> Nullable<Text>,
-        calversion -> Nullable<Text>,
     }
 }
 
 diesel::allow_tables_to_appear_in_same_query!(
     batch_t,
     config_t,
+    decoding_t,
     rounds_t,
     samples_t,
     summary_t,
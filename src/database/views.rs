@@ -1,5 +1,5 @@
 diesel::table! {
-    rounds_v (session, round, role) {
+    rounds_v (session, round, role, name, channel) {
         session -> Timestamp,
         round -> Integer,
         role -> Text,
@@ -14,7 +14,8 @@ diesel::table! {
         nsamples -> Nullable<Integer>,
         duration -> Nullable<Float>,
         model -> Nullable<Text>,
-        name -> Nullable<Text>,
+        name -> Text,
+        channel -> Integer,
         mac -> Nullable<Text>,
         nrounds -> Nullable<Integer>,
         upd_flag -> Nullable<Integer>,
@@ -22,13 +23,14 @@ diesel::table! {
 }
 
 diesel::table! {
-    summary_v (session, role) {
+    summary_v (session, role, name, channel) {
         session -> Timestamp,
         role -> Text,
         calibration -> Nullable<Text>,
         calversion -> Nullable<Text>,
         model -> Nullable<Text>,
-        name -> Nullable<Text>,
+        name -> Text,
+        channel -> Integer,
         mac -> Nullable<Text>,
         firmware -> Nullable<Text>,
         sensor -> Nullable<Text>,
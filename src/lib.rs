@@ -9,14 +9,13 @@ use std::env;
 
 // let _tstamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
 pub type Timestamp = DateTime<Utc>;
-pub type Sample = (Timestamp, photometer::payload::Payload);
+pub type Sample = (Timestamp, Role, photometer::payload::Payload);
 
-pub enum Model {
-    Tessw,
-    Tas,
-    Tessp,
-}
+// A test photometer model, one of photometer::profile::MODELS
+#[derive(Debug, Clone, Copy)]
+pub struct Model(&'static photometer::profile::Profile);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Role {
     Refe,
     Test,
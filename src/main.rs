@@ -1,13 +1,18 @@
 use anyhow::Result;
 use argparse::{Cli, Commands, Operation};
 use chrono::prelude::*;
+use std::path::PathBuf;
 use tokio::signal;
 use tokio::sync::mpsc;
-use tracing::info;
+use tokio::task::JoinHandle;
+use tokio::time::Duration;
+use tracing::{info, warn};
 use zptess::database::Pool;
 use zptess::photometer::discovery::Info;
-use zptess::photometer::payload::Payload;
-use zptess::Timestamp;
+use zptess::photometer::profile;
+use zptess::photometer::transport::capture::{self, Capture};
+use zptess::photometer::transport::Endpoint;
+use zptess::Sample;
 use zptess::{photometer, statistics};
 
 // Include these modules as part of the binary crate, not the library crate
@@ -31,86 +36,265 @@ struct Cli {
 
 */
 
-async fn do_read(model: argparse::Model, role: argparse::Role, pool: &Pool) -> Result<()> {
+// The capture task ends once the reading tasks drop their handles
+async fn start_capture(
+    path: Option<PathBuf>,
+) -> Result<(Option<Capture>, Option<JoinHandle<Result<()>>>)> {
+    match path {
+        Some(path) => {
+            let (capture, fcapture) = capture::start(path).await?;
+            Ok((Some(capture), Some(fcapture)))
+        }
+        None => Ok((None, None)),
+    }
+}
+
+async fn do_read(
+    model: argparse::Model,
+    role: argparse::Role,
+    save: bool,
+    endpoints: argparse::Endpoints,
+    capture: Option<PathBuf>,
+    silence: Duration,
+    pool: &Pool,
+) -> Result<()> {
     let model = model.map_model();
-    let (tx1, rx) = mpsc::channel::<(Timestamp, Payload)>(32);
-    let tx2 = tx1.clone();
+    let test_profile = model.profile();
+    let (capture, fcapture) = start_capture(capture).await?;
+    let (tx, rx) = mpsc::channel::<Sample>(32);
     let mut test_info: Option<Info> = None;
     let mut ref_info: Option<Info> = None;
+    let mut readers = Vec::with_capacity(2);
     match role {
         argparse::Role::Test => {
-            let _test_info = photometer::discover_test(&model).await?;
+            let test_endpoint =
+                photometer::endpoint(pool, false, model.profile(), endpoints.test_endpoint, None)
+                    .await?;
+            let _test_info = photometer::discover_test(&model, &test_endpoint).await?;
             info!("{_test_info:#?}");
             test_info = Some(_test_info);
-            let _ftest = tokio::spawn(async move {
-                let _ = photometer::reading_task(tx1, false).await; // again: pool1 is moved to the task and gets out of scope
-            });
+            let tx1 = tx.clone();
+            let capture1 = capture.clone();
+            readers.push(tokio::spawn(photometer::reading_task(
+                tx1,
+                false,
+                test_profile,
+                test_endpoint,
+                capture1,
+                None,
+                silence,
+            )));
         }
         argparse::Role::Ref => {
             let _ref_info = photometer::discover_ref(pool).await?;
             info!("{_ref_info:#?}");
             ref_info = Some(_ref_info);
-            let _fref = tokio::spawn(async move {
-                let _ = photometer::reading_task(tx2, true).await; // again: pool1 is moved to the task and gets out of scope
-            });
+            let ref_endpoint = photometer::endpoint(
+                pool,
+                true,
+                &profile::REFERENCE,
+                endpoints.ref_endpoint,
+                None,
+            )
+            .await?;
+            let tx2 = tx.clone();
+            let capture2 = capture.clone();
+            readers.push(tokio::spawn(photometer::reading_task(
+                tx2,
+                true,
+                &profile::REFERENCE,
+                ref_endpoint,
+                capture2,
+                None,
+                silence,
+            )));
         }
         argparse::Role::Both => {
-            let _test_info = photometer::discover_test(&model).await?;
+            let test_endpoint =
+                photometer::endpoint(pool, false, model.profile(), endpoints.test_endpoint, None)
+                    .await?;
+            let ref_endpoint = photometer::endpoint(
+                pool,
+                true,
+                &profile::REFERENCE,
+                endpoints.ref_endpoint,
+                Some(&test_endpoint),
+            )
+            .await?;
+            let _test_info = photometer::discover_test(&model, &test_endpoint).await?;
             info!("{_test_info:#?}");
             test_info = Some(_test_info);
             let _ref_info = photometer::discover_ref(pool).await?;
             info!("{_ref_info:#?}");
             ref_info = Some(_ref_info);
-            let _ftest = tokio::spawn(async move {
-                let _ = photometer::reading_task(tx1, false).await; // pool1 is moved to the task and gets out of scope
-            });
-            let _fref = tokio::spawn(async move {
-                let _ = photometer::reading_task(tx2, true).await; // again: pool1 is moved to the task and gets out of scope
-            });
+            let tx1 = tx.clone();
+            let capture1 = capture.clone();
+            readers.push(tokio::spawn(photometer::reading_task(
+                tx1,
+                false,
+                test_profile,
+                test_endpoint,
+                capture1,
+                None,
+                silence,
+            )));
+            let tx2 = tx.clone();
+            let capture2 = capture.clone();
+            readers.push(tokio::spawn(photometer::reading_task(
+                tx2,
+                true,
+                &profile::REFERENCE,
+                ref_endpoint,
+                capture2,
+                None,
+                silence,
+            )));
         }
     }
+    // Only the reading tasks keep the samples channel and the capture file open
+    drop(tx);
+    drop(capture);
+    let (recorder, frecorder) = if save {
+        let (tx3, rx3) = mpsc::channel::<Sample>(1024);
+        let pool2 = pool.clone();
+        let names = [&ref_info, &test_info]
+            .map(|info| info.as_ref().map(|i| i.name.clone()).unwrap_or_default());
+        let frecorder = tokio::spawn(async move {
+            let _ = statistics::recording_task(pool2, Utc::now(), rx3, names).await;
+        });
+        (
+            Some(statistics::recorder::Recorder::new(tx3)),
+            Some(frecorder),
+        )
+    } else {
+        (None, None)
+    };
     let pool1 = pool.clone();
-    tokio::spawn(async move {
-        let _ = statistics::reading_task(pool1, rx, 9, ref_info, test_info).await;
+    let fstats = tokio::spawn(async move {
+        let _ = statistics::reading_task(pool1, rx, 9, ref_info, test_info, recorder).await;
         // again: pool1 is moved to the task and gets out of scope
     });
     signal::ctrl_c().await?;
-    Ok(())
+    // Stopping the reading tasks closes the samples channel, so the statistics task
+    // ends and drops the recorder, which lets the recording task write its last batch
+    for reader in readers.iter() {
+        reader.abort();
+    }
+    // A reading task that ended on its own tells why, once the others are stopped
+    let mut ended = Ok(());
+    for reader in readers {
+        match reader.await {
+            Ok(Err(e)) if ended.is_ok() => ended = Err(e),
+            Err(e) if e.is_panic() && ended.is_ok() => ended = Err(e.into()),
+            _ => {}
+        }
+    }
+    fstats.await?;
+    if let Some(frecorder) = frecorder {
+        frecorder.await?;
+    }
+    if let Some(fcapture) = fcapture {
+        fcapture.await??;
+    }
+    ended
 }
 
 async fn do_calibrate(
     model: argparse::Model,
     pool: &Pool,
-    _update: bool,
-    _test: bool,
-    _author: Option<String>,
+    options: statistics::SessionOptions,
+    endpoints: argparse::Endpoints,
+    capture: Option<PathBuf>,
+    silence: Duration,
+    ntests: usize,
 ) -> Result<()> {
-    let session = Utc::now();
     let model = model.map_model();
-    let test_info = photometer::discover_test(&model).await?;
+    let test_profile = model.profile();
+    let mut test_endpoint =
+        photometer::endpoint(pool, false, model.profile(), endpoints.test_endpoint, None).await?;
+    // Several test photometers sending to the same port is what is expected then
+    if let Endpoint::Udp(_, _, ref mut filter) = test_endpoint {
+        filter.shared = ntests > 1;
+    }
+    let ref_endpoint = photometer::endpoint(
+        pool,
+        true,
+        &profile::REFERENCE,
+        endpoints.ref_endpoint,
+        Some(&test_endpoint),
+    )
+    .await?;
+    let test_info = photometer::discover_test(&model, &test_endpoint).await?;
     info!("{test_info:#?}");
     let ref_info = photometer::discover_ref(pool).await?;
     info!("{ref_info:#?}");
-    let (tx1, rx) = mpsc::channel::<(Timestamp, Payload)>(32);
+    let (capture1, fcapture) = start_capture(capture).await?;
+    let capture2 = capture1.clone();
+    let (tx1, rx) = mpsc::channel::<Sample>(32);
     let tx2 = tx1.clone();
-    let ftest = tokio::spawn(async move {
-        let _ = photometer::reading_task(tx1, false).await;
-    });
-    let fref = tokio::spawn(async move {
-        let _ = photometer::reading_task(tx2, true).await;
-    });
+    let (ev_tx1, ev_rx) = mpsc::channel::<photometer::Event>(8);
+    let ev_tx2 = ev_tx1.clone();
+    let ftest = tokio::spawn(photometer::reading_task(
+        tx1,
+        false,
+        test_profile,
+        test_endpoint,
+        capture1,
+        Some(ev_tx1),
+        silence,
+    ));
+    let fref = tokio::spawn(photometer::reading_task(
+        tx2,
+        true,
+        &profile::REFERENCE,
+        ref_endpoint,
+        capture2,
+        Some(ev_tx2),
+        silence,
+    ));
     let pool1 = pool.clone();
-    let fstats = tokio::spawn(async move {
-        let result =
-            statistics::calibration_task(pool1, session, rx, 9, 5, 5000, ref_info, test_info).await;
-        let zp = result.expect("Calibrated ZP");
-        if _update {
-            photometer::write_zero_point(&model, zp)
-                .await
-                .expect("Written ZP OK");
+    let update = options.update;
+    let (session, persist) = (options.session, options.persist);
+    let dao = statistics::dao::Dao::new(pool.clone());
+    let discovered = test_info.name.clone();
+    // Each channel of a multi-channel photometer is calibrated as a test photometer
+    let ntracks = ntests * test_profile.channels;
+    let rounds = statistics::RoundOptions {
+        window: 9,
+        nrounds: 5,
+        ntests: ntracks,
+        millis: 5000,
+        profile: test_profile,
+        silence,
+    };
+    let fstats = tokio::spawn(statistics::calibration_task(
+        pool1, rx, ev_rx, ref_info, test_info, rounds, options,
+    ));
+    // The reading tasks end once the calibration is over
+    let (ftest, fref, result) = tokio::join!(ftest, fref, fstats);
+    if let Some(fcapture) = fcapture {
+        fcapture.await??;
+    }
+    // The calibration error, if any, names the photometer whose readings ended
+    let zero_points = result??;
+    ftest??;
+    fref??;
+    if update {
+        // Only the discovered photometer can be updated
+        for (name, channel, zp) in zero_points {
+            if ntests > 1 && name != discovered {
+                warn!(
+                    "Zero point {:.02} not written to {}, update it by hand",
+                    zp, name
+                );
+                continue;
+            }
+            photometer::write_zero_point(&model, zp).await?;
+            if persist {
+                dao.zero_point_written(&session, &name, channel).await?;
+            }
         }
-    });
-    futures::future::join_all(vec![ftest, fref, fstats]).await;
+    }
     info!("All tasks terminated");
     Ok(())
 }
@@ -136,30 +320,65 @@ async fn main() -> Result<()> {
     match command {
         Commands::Calibrate {
             model,
-            // filter,
-            // plug,
-            // box_model,
+            filter,
+            plug,
+            box_model,
             author,
             operation,
-            ..
+            endpoints,
+            capture,
+            silence,
+            pause_on_silence,
+            tests,
         } => {
             let Operation {
                 dry_run,
                 update,
                 test,
             } = operation;
+            if update {
+                photometer::check_writer(&model.map_model())?;
+            }
 
             //let mut g_author: Option<String> = None;
             // Display photometer info and bail out
             if dry_run {
                 let model = model.map_model();
-                let test_info = photometer::discover_test(&model).await?;
+                let test_endpoint = photometer::endpoint(
+                    &pool,
+                    false,
+                    model.profile(),
+                    endpoints.test_endpoint,
+                    None,
+                )
+                .await?;
+                let test_info = photometer::discover_test(&model, &test_endpoint).await?;
                 info!("{test_info:#?}");
                 return Ok(());
             }
             // Join the vector of strings into a single string
             let author = author.map(|a| a.join(" "));
-            do_calibrate(model, &pool, update, test, author).await?
+            let options = statistics::SessionOptions {
+                session: Utc::now(),
+                author,
+                filter,
+                plug,
+                box_model,
+                update,
+                persist: !test,
+                pause_on_silence,
+            };
+            let silence = Duration::from_secs(silence);
+            do_calibrate(
+                model,
+                &pool,
+                options,
+                endpoints,
+                capture,
+                silence,
+                tests as usize,
+            )
+            .await?
         }
 
         Commands::Migrate {} => {
@@ -172,8 +391,16 @@ async fn main() -> Result<()> {
             return Ok(());
         }
 
-        Commands::Read { model, role } => {
-            do_read(model, role, &pool).await?;
+        Commands::Read {
+            model,
+            role,
+            save,
+            endpoints,
+            capture,
+            silence,
+        } => {
+            let silence = Duration::from_secs(silence);
+            do_read(model, role, save, endpoints, capture, silence, &pool).await?;
             return Ok(());
         }
     }
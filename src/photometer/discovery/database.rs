@@ -1,5 +1,6 @@
 use super::Info;
 use crate::database::{models::Config, Db, Pool};
+use crate::photometer::profile;
 use anyhow::Result;
 use diesel::prelude::*;
 use tokio::task;
@@ -28,7 +29,7 @@ impl<'a> Discoverer<'a> {
             task::spawn_blocking(move || sql.load(&mut conn1).expect("Error loading config"))
                 .await?;
 
-        let mut info = Info::new();
+        let mut info = Info::new(&profile::REFERENCE);
         for item in results.iter() {
             match item.property.as_str() {
                 "model" => info.model = item.value.clone(),
@@ -43,4 +44,20 @@ impl<'a> Discoverer<'a> {
         }
         Ok(info)
     }
+
+    // Where a photometer is attached, if configured in the given device section
+    pub async fn endpoint(&self, device_section: &str) -> Result<Option<String>> {
+        use crate::database::schema::config_t::dsl::*;
+        let sql = config_t
+            .filter(section.eq(device_section.to_string()))
+            .filter(property.eq("endpoint"))
+            .select(value);
+
+        debug!("{:?}", diesel::debug_query::<Db, _>(&sql).to_string());
+        let mut conn1 = self.pool.get()?;
+        let results: Vec<String> =
+            task::spawn_blocking(move || sql.load(&mut conn1).expect("Error loading config"))
+                .await?;
+        Ok(results.into_iter().next())
+    }
 }
@@ -1,4 +1,5 @@
 use super::Info;
+use crate::photometer::profile::Profile;
 use anyhow::Result;
 use regex::Regex;
 use reqwest;
@@ -21,18 +22,14 @@ const URL_GET_INFO: &str = "http://192.168.4.1/config";
 
 #[derive(Debug)]
 pub struct Discoverer {
+    profile: &'static Profile,
     re: Vec<Regex>,
 }
 
-impl Default for Discoverer {
-    fn default() -> Self {
-        Self::new()
-    }
-}
-
 impl Discoverer {
-    pub fn new() -> Self {
+    pub fn new(profile: &'static Profile) -> Self {
         Self {
+            profile,
             re: vec![
                 Regex::new(NAME).unwrap(),
                 Regex::new(MAC).unwrap(),
@@ -44,7 +41,7 @@ impl Discoverer {
     }
 
     fn decode(&self, body: &str) -> Result<Info> {
-        let mut info = Info::new();
+        let mut info = Info::new(self.profile);
         for (i, re) in self.re.iter().enumerate() {
             if let Some(result) = re.captures(body) {
                 match i {
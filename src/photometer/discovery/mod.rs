@@ -1,7 +1,11 @@
 pub mod database;
 pub mod http;
+pub mod readings;
 
-#[derive(Debug)]
+use super::payload::Json;
+use super::profile::Profile;
+
+#[derive(Debug, Clone)]
 pub struct Info {
     pub model: String,
     pub name: String,
@@ -10,18 +14,29 @@ pub struct Info {
     pub sensor: String,
     pub zp: f32,
     pub freq_offset: f32,
+    pub channel_zps: Vec<f32>, // multi-channel photometers only
 }
 
 impl Info {
-    fn new() -> Self {
+    fn new(profile: &Profile) -> Self {
         Self {
-            model: "TESS-W".into(),
+            model: profile.model.into(),
             name: "".into(),
             mac: "".into(),
             firmware: "".into(),
-            sensor: "TSL237".into(),
+            sensor: profile.sensor.into(),
             zp: 0.0,
             freq_offset: 0.0,
+            channel_zps: Vec::new(),
+        }
+    }
+
+    // What a photometer tells about itself in its readings, when not discovered otherwise
+    pub fn from_reading(profile: &Profile, payload: &Json) -> Self {
+        Self {
+            name: payload.name.clone(),
+            zp: payload.ZP.unwrap_or_default(),
+            ..Self::new(profile)
         }
     }
 }
@@ -0,0 +1,113 @@
+// Photometers that can't be queried tell enough about themselves in their readings
+use super::Info;
+use crate::photometer::payload::{Decoder, Json, Payload};
+use crate::photometer::profile::Profile;
+use crate::photometer::transport::{capture::Record, Endpoint, RawSample, Transport};
+use anyhow::{bail, Result};
+use chrono::Utc;
+use std::io;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::time::{timeout, Duration};
+use tracing::info;
+
+const LISTEN_SECS: u64 = 180; // field photometers publish a reading every minute or so
+
+// Name and zero points found in a line of readings, one payload per channel
+pub fn decode(profile: &Profile, line: &str) -> Option<Info> {
+    let payloads = match Decoder::sniff(line)? {
+        Decoder::Json(_) => vec![serde_json::from_str::<Json>(line).ok()?],
+        Decoder::Tess4c(mut decoder) => decoder
+            .decode(Utc::now(), line)
+            .ok()?
+            .into_iter()
+            .filter_map(|(_, payload)| match payload {
+                Payload::Json(json) => Some(json),
+                Payload::Cristogg(_) => None,
+            })
+            .collect(),
+        Decoder::Cristogg(_) => return None,
+    };
+    let first = payloads.first()?;
+    let mut info = Info::from_reading(profile, first);
+    if payloads.len() > 1 {
+        info.channel_zps = payloads
+            .iter()
+            .map(|json| json.ZP.unwrap_or_default())
+            .collect();
+    }
+    Some(info)
+}
+
+// Replayed, simulated and field photometers are described by their readings:
+// the first one in the capture file, the simulation parameters or the first one
+// published to the broker. MAC and firmware stay unknown.
+pub struct Discoverer<'a> {
+    endpoint: &'a Endpoint,
+    profile: &'static Profile,
+}
+
+impl<'a> Discoverer<'a> {
+    pub fn new(endpoint: &'a Endpoint, profile: &'static Profile) -> Self {
+        Self { endpoint, profile }
+    }
+
+    pub async fn discover(&self) -> Result<Info> {
+        match self.endpoint {
+            Endpoint::Replay(path, _) => self.replayed(path).await,
+            Endpoint::Simulator(params) => Ok(Info {
+                name: params.name.clone(),
+                zp: params.zp,
+                ..Info::new(self.profile)
+            }),
+            _ => self.listen().await,
+        }
+    }
+
+    async fn replayed(&self, path: &Path) -> Result<Info> {
+        let file = File::open(path).await?;
+        let mut lines = BufReader::new(file).lines();
+        while let Some(line) = lines.next_line().await? {
+            let Ok(record) = serde_json::from_str::<Record>(&line) else {
+                continue;
+            };
+            if record.role != "test" {
+                continue;
+            }
+            if let Some(info) = decode(self.profile, &record.line) {
+                return Ok(info);
+            }
+        }
+        bail!(
+            "No {} readings telling the photometer name in capture file {}",
+            self.profile.model,
+            path.display()
+        )
+    }
+
+    async fn listen(&self) -> Result<Info> {
+        info!(
+            "Waiting up to {} s for a {} reading on {}",
+            LISTEN_SECS, self.profile.model, self.endpoint
+        );
+        let mut transport = Transport::open(self.endpoint, "test").await?;
+        let listening = async {
+            loop {
+                let RawSample(_, line) = transport.reading().await?;
+                if let Some(info) = decode(self.profile, &line) {
+                    return Ok::<_, io::Error>(info);
+                }
+            }
+        };
+        match timeout(Duration::from_secs(LISTEN_SECS), listening).await {
+            Ok(info) => Ok(info?),
+            Err(_) => bail!(
+                "No {} reading telling the photometer name on {} in {} s",
+                self.profile.model,
+                self.endpoint,
+                LISTEN_SECS
+            ),
+        }
+    }
+}
@@ -1,41 +1,182 @@
 pub mod discovery;
 pub mod payload;
+pub mod profile;
 pub mod transport;
 pub mod update;
 
 use super::database::Pool;
-use super::{Model, Sample};
-use anyhow::Result;
+use super::{Model, Role, Sample, Timestamp};
+use anyhow::{anyhow, bail, Result};
+use chrono::prelude::*;
 use discovery::Info;
-use payload::Decoder;
+use payload::{DecodeError, DecodeStats, Decoder, Sequence};
+use profile::{Format, Link, Profile};
+use std::collections::hash_map::{Entry, HashMap};
+use std::io::ErrorKind;
 use tokio::sync::mpsc::Sender;
-use tracing::{debug, info};
-use transport::serial;
-use transport::udp;
-use transport::{RawSample, Transport};
-
-fn choose_decoder_type(is_ref_phot: bool) -> Decoder {
-    if !is_ref_phot {
-        Decoder::Json(payload::json::Decoder::new())
-    } else {
-        Decoder::Cristogg(payload::cristogg::Decoder::new())
+use tokio::time::{sleep, timeout, Duration};
+use tracing::{debug, error, info, warn};
+use transport::{capture::Capture, serial, udp, Endpoint, RawSample, Transport};
+
+const RECONNECT_MIN_SECS: u64 = 1; // first wait before reopening a transport
+const RECONNECT_MAX_SECS: u64 = 30; // the wait doubles on each failure up to this
+pub const DEFAULT_SILENCE_SECS: u64 = 60; // no readings for this long means a silent photometer
+const SNIFF_LINES: usize = 5; // unrecognized lines before warning about an unknown payload format
+const EVENTS_ROOM: usize = 4; // event slots rejected line reports leave to link status changes
+
+// Photometer link status changes, so that consumers may react to them
+#[derive(Debug, Clone)]
+pub enum Status {
+    Disconnected(String), // with the reason
+    Reconnected,
+    Silent(u64),                   // no readings for this number of seconds
+    Resumed,                       // readings arrive again after being silent
+    Rejected(String, DecodeStats), // lines from the named photometer yielding no samples so far
+    Sequence(String, Sequence),    // readings lost or the named photometer rebooted
+    Ended(Option<String>),         // no more readings, with the error that ended them, if any
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub tstamp: Timestamp, // of the reading telling it, if any, so that replays keep their own time
+    pub role: Role,
+    pub status: Status,
+}
+
+async fn notify(events: &Option<Sender<Event>>, tstamp: Timestamp, role: Role, status: Status) {
+    if let Some(events) = events {
+        let event = Event {
+            tstamp,
+            role,
+            status,
+        };
+        let _ = events.send(event).await;
+    }
+}
+
+// Running counts of the rejected lines, reported without waiting on the consumer,
+// so that a burst of garbage never stalls the reading task nor crowds out other events.
+// A report not taken is superseded by the next one.
+struct Rejections {
+    reported: HashMap<String, u32>, // rejected lines already told, by photometer name
+}
+
+impl Rejections {
+    fn new() -> Self {
+        Self {
+            reported: HashMap::new(),
+        }
+    }
+
+    // Photometers with rejected lines not told yet
+    fn unreported<'a>(
+        &'a self,
+        stats: &'a HashMap<String, DecodeStats>,
+    ) -> impl Iterator<Item = (&'a String, &'a DecodeStats)> {
+        stats.iter().filter(|(key, counter)| {
+            self.reported.get(*key).copied().unwrap_or_default() != counter.rejected()
+        })
+    }
+
+    fn report(
+        &mut self,
+        events: &Option<Sender<Event>>,
+        role: Role,
+        stats: &HashMap<String, DecodeStats>,
+    ) {
+        let Some(events) = events else {
+            return;
+        };
+        let mut told = Vec::new();
+        for (key, counter) in self.unreported(stats) {
+            if events.capacity() <= EVENTS_ROOM {
+                break; // consumer lagging behind, told on a later report
+            }
+            let event = Event {
+                tstamp: Utc::now(),
+                role,
+                status: Status::Rejected(key.clone(), counter.clone()),
+            };
+            if events.try_send(event).is_err() {
+                break;
+            }
+            told.push((key.clone(), counter.rejected()));
+        }
+        self.reported.extend(told);
+    }
+
+    // The last counts are told once the readings are over, whatever it takes
+    async fn flush(
+        self,
+        events: &Option<Sender<Event>>,
+        role: Role,
+        stats: &HashMap<String, DecodeStats>,
+    ) {
+        let unreported = self
+            .unreported(stats)
+            .map(|(key, counter)| (key.clone(), counter.clone()))
+            .collect::<Vec<_>>();
+        for (key, counter) in unreported {
+            notify(events, Utc::now(), role, Status::Rejected(key, counter)).await;
+        }
+    }
+}
+
+// Keeps trying to reopen the transport with an exponential backoff.
+// Gives up only when nobody is listening to the readings anymore.
+async fn reconnect(endpoint: &Endpoint, role: &str, chan: &Sender<Sample>) -> Option<Transport> {
+    let mut wait = RECONNECT_MIN_SECS;
+    while !chan.is_closed() {
+        sleep(Duration::from_secs(wait)).await;
+        match Transport::open(endpoint, role).await {
+            Ok(transport) => return Some(transport),
+            Err(e) => {
+                warn!(
+                    "Reopening {} failed, retrying in {} s: {}",
+                    endpoint, wait, e
+                );
+                wait = (wait * 2).min(RECONNECT_MAX_SECS);
+            }
+        }
     }
+    None
 }
 
-async fn choose_transport_type(is_ref_phot: bool) -> transport::Transport {
-    if !is_ref_phot {
-        Transport::Udp(udp::Transport::new(2255).await.expect("New UDP Transport"))
+// The decoder for a new stream is chosen by the format of its first recognized line.
+// The model profile only tells which format to expect.
+fn sniff_decoder(label: &str, who: &str, expected: Format, line: &str) -> Option<Decoder> {
+    let decoder = Decoder::sniff(line)?;
+    let format = decoder.format();
+    if format == expected {
+        info!("{} photometer {} sends {} payloads", label, who, format);
     } else {
-        Transport::Serial(
-            serial::Transport::new(9600)
-                .await
-                .expect("New serial Transport"),
-        )
+        warn!(
+            "{} photometer {} sends {} payloads, {} expected",
+            label, who, format, expected
+        );
     }
+    Some(decoder)
 }
 
-pub async fn discover_test(_model: &Model) -> Result<Info> {
-    discovery::http::Discoverer::new().discover().await
+// Replayed, simulated and field photometers are described by their readings
+// instead of being queried, and so are serial photometers, as their command
+// set is not documented
+pub async fn discover_test(model: &Model, endpoint: &Endpoint) -> Result<Info> {
+    let profile = model.profile();
+    match (profile.info, endpoint) {
+        (_, Endpoint::Replay(..) | Endpoint::Simulator(_) | Endpoint::Mqtt(..)) => {
+            discovery::readings::Discoverer::new(endpoint, profile)
+                .discover()
+                .await
+        }
+        (Link::Http, _) => discovery::http::Discoverer::new(profile).discover().await,
+        // Their command set is not documented, see NOTES.md
+        (Link::Serial, _) => bail!(
+            "{} photometers can't be discovered yet, their serial command set is not documented",
+            profile.model
+        ),
+        (Link::Database, _) => bail!("{} photometers can't be discovered", profile.model),
+    }
 }
 
 pub async fn discover_ref(pool: &Pool) -> Result<Info> {
@@ -43,27 +184,259 @@ pub async fn discover_ref(pool: &Pool) -> Result<Info> {
     discoverer.discover().await
 }
 
-pub async fn write_zero_point(_model: &Model, zp: f32) -> Result<()> {
+// Command line endpoint takes precedence over the device section in the database
+// and both over the photometer model defaults. An automatically detected serial
+// port must send the payload format of the profile and not be the one taken
+// by the other photometer.
+pub async fn endpoint(
+    pool: &Pool,
+    is_ref_phot: bool,
+    profile: &Profile,
+    cli: Option<Endpoint>,
+    taken: Option<&Endpoint>,
+) -> Result<Endpoint> {
+    let (label, section) = if is_ref_phot {
+        ("Ref.", "ref-device")
+    } else {
+        ("Test", "test-device")
+    };
+    let endpoint = match cli {
+        Some(endpoint) => endpoint,
+        None => {
+            let discoverer = discovery::database::Discoverer::new(pool);
+            match discoverer.endpoint(section).await? {
+                Some(value) => value.parse::<Endpoint>()?,
+                None => profile.default_endpoint(),
+            }
+        }
+    };
+    let endpoint = match endpoint {
+        Endpoint::Serial(port) if port.device == serial::AUTO_DEVICE => {
+            let taken = match taken {
+                Some(Endpoint::Serial(other)) => Some(other.device.as_str()),
+                _ => None,
+            };
+            let port = serial::detect(port.baud, taken, |line| {
+                Decoder::sniff(line).is_some_and(|decoder| decoder.format() == profile.format)
+            })
+            .await?;
+            Endpoint::Serial(port)
+        }
+        _ => endpoint,
+    };
+    info!("{} photometer endpoint is {}", label, endpoint);
+    Ok(endpoint)
+}
+
+// Checked up front, so that an update that can't be done doesn't wait for the end of a calibration
+pub fn check_writer(model: &Model) -> Result<()> {
+    let profile = model.profile();
+    match profile.writer {
+        // Neither the per channel zero point query of the web server nor the page
+        // showing them is documented, see NOTES.md
+        Link::Http if profile.channels > 1 => bail!(
+            "{} zero points can't be written yet, their web server queries are not documented",
+            profile.model
+        ),
+        Link::Http => Ok(()),
+        // The serial command set is not documented, see NOTES.md
+        Link::Serial => bail!(
+            "{} zero points can't be written through the serial port yet, their command set is not documented",
+            profile.model
+        ),
+        Link::Database => bail!("{} zero points can't be written", profile.model),
+    }
+}
+
+pub async fn write_zero_point(model: &Model, zp: f32) -> Result<()> {
+    check_writer(model)?;
     update::http::Updater::new().update_zp(zp).await?;
     info!("Updated Zero Point {:.02}", zp);
     Ok(())
 }
 
-pub async fn reading_task(chan: Sender<Sample>, is_ref_phot: bool) -> Result<()> {
-    let mut transport = choose_transport_type(is_ref_phot).await;
-    let mut decoder = choose_decoder_type(is_ref_phot);
-    loop {
-        let RawSample(tstamp, raw_bytes) = transport.reading().await?;
+// Consumers are told when the readings are over, whatever the reason,
+// so that nobody keeps waiting for them
+pub async fn reading_task(
+    chan: Sender<Sample>,
+    is_ref_phot: bool,
+    profile: &'static Profile,
+    endpoint: Endpoint,
+    capture: Option<Capture>,
+    events: Option<Sender<Event>>,
+    silence: Duration,
+) -> Result<()> {
+    let result = readings(
+        &chan,
+        is_ref_phot,
+        profile,
+        &endpoint,
+        capture,
+        &events,
+        silence,
+    )
+    .await;
+    let reason = result.as_ref().err().map(|e| format!("{e:#}"));
+    let event_role = if is_ref_phot { Role::Refe } else { Role::Test };
+    notify(&events, Utc::now(), event_role, Status::Ended(reason)).await;
+    result
+}
+
+async fn readings(
+    chan: &Sender<Sample>,
+    is_ref_phot: bool,
+    profile: &'static Profile,
+    endpoint: &Endpoint,
+    capture: Option<Capture>,
+    events: &Option<Sender<Event>>,
+    silence: Duration,
+) -> Result<()> {
+    let role = if is_ref_phot { "ref" } else { "test" };
+    let label = if is_ref_phot { "Ref." } else { "Test" };
+    let event_role = if is_ref_phot { Role::Refe } else { Role::Test };
+    let transport_name = endpoint.to_string();
+    let mut transport = match Transport::open(endpoint, role).await {
+        Ok(transport) => transport,
+        Err(e) => {
+            error!("Unable to open {}: {}", endpoint, e);
+            return Err(anyhow!(e).context(format!("Unable to open {}", endpoint)));
+        }
+    };
+    // One decoder per photometer name, as several test photometers may share a stream
+    let mut decoders = HashMap::<String, Decoder>::new();
+    let mut unknown = 0; // lines not recognized before finding a decoder
+    let mut stats = HashMap::<String, DecodeStats>::new(); // by photometer name, as decoders
+    let mut rejections = Rejections::new(); // told as running counts, not line by line
+    let mut silent = false;
+    let mut reconnected = false;
+    'reading: loop {
+        rejections.report(events, event_role, &stats);
+        let reading = match timeout(silence, transport.reading()).await {
+            Ok(reading) => reading,
+            Err(_) if chan.is_closed() => break,
+            Err(_) => {
+                if !silent {
+                    warn!(
+                        "{} photometer silent for {} s on {}",
+                        label,
+                        silence.as_secs(),
+                        endpoint
+                    );
+                    let status = Status::Silent(silence.as_secs());
+                    notify(events, Utc::now(), event_role, status).await;
+                    silent = true;
+                }
+                continue;
+            }
+        };
+        if let (true, Ok(RawSample(tstamp, _))) = (silent, &reading) {
+            info!("{} photometer readings resumed on {}", label, endpoint);
+            notify(events, *tstamp, event_role, Status::Resumed).await;
+            silent = false;
+        }
+        // Told with the first reading after reconnecting
+        if let (true, Ok(RawSample(tstamp, _))) = (reconnected, &reading) {
+            notify(events, *tstamp, event_role, Status::Reconnected).await;
+            reconnected = false;
+        }
+        let raw_sample = match reading {
+            Ok(raw_sample) => raw_sample,
+            Err(e) if transport.is_reconnectable() => {
+                warn!("{} disconnected: {}", endpoint, e);
+                let status = Status::Disconnected(e.to_string());
+                notify(events, Utc::now(), event_role, status).await;
+                match reconnect(endpoint, role, chan).await {
+                    Some(reopened) => {
+                        info!("{} reconnected", endpoint);
+                        reconnected = true;
+                        transport = reopened;
+                        decoders.clear(); // no duplicate filtering across links, sniff again
+                        continue;
+                    }
+                    None => break,
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                info!("{}: {}", endpoint, e);
+                break;
+            }
+            Err(e) => {
+                error!("Reading from {}: {}", endpoint, e);
+                return Err(e.into());
+            }
+        };
+        if let Some(ref capture) = capture {
+            capture.record(role, &transport_name, &raw_sample);
+        }
+        let RawSample(tstamp, raw_bytes) = raw_sample;
         //info!("{raw_bytes:?}");
-        match decoder.decode(tstamp, &raw_bytes) {
-            Ok((tstamp, payload)) => match chan.send((tstamp, payload)).await {
-                Ok(_) => {}
-                Err(_) => {
-                    break;
+        let key = udp::photometer_name(&raw_bytes).unwrap_or_default();
+        let decoder = match decoders.entry(key.clone()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let who = match entry.key().as_str() {
+                    "" => transport_name.as_str(),
+                    name => name,
+                };
+                match sniff_decoder(label, who, profile.format, &raw_bytes) {
+                    Some(decoder) => entry.insert(decoder),
+                    // A truncated or corrupted JSON datagram, not a different format
+                    None if raw_bytes.trim_start().starts_with('{') => {
+                        debug!("Malformed JSON payload: {raw_bytes:?}");
+                        let e = DecodeError::Malformed("not a whole JSON object".to_string());
+                        stats.entry(key).or_default().count(&e);
+                        continue;
+                    }
+                    None => {
+                        debug!("Unknown payload format: {raw_bytes:?}");
+                        let e = DecodeError::UnknownFormat;
+                        stats.entry(key).or_default().count(&e);
+                        unknown += 1;
+                        if unknown == SNIFF_LINES {
+                            warn!(
+                                "{} photometer on {} sends no known payload format in {} lines",
+                                label, endpoint, SNIFF_LINES
+                            );
+                        }
+                        continue;
+                    }
                 }
-            },
-            Err(e) => debug!("{e:?}"),
+            }
+        };
+        let outcome = decoder.decode(tstamp, &raw_bytes);
+        let sequence = decoder.take_sequence();
+        let counter = stats.entry(key.clone()).or_default();
+        if let Some(sequence) = sequence {
+            match sequence {
+                Sequence::Lost(n) => debug!("{} photometer {} lost {} readings", label, key, n),
+                Sequence::Reboot => warn!("{} photometer {} rebooted", label, key),
+            }
+            counter.sequence(sequence);
+            let status = Status::Sequence(key.clone(), sequence);
+            notify(events, tstamp, event_role, status).await;
         }
+        match outcome {
+            Ok(samples) => {
+                counter.accepted += 1;
+                for (tstamp, payload) in samples {
+                    if chan.send((tstamp, event_role, payload)).await.is_err() {
+                        break 'reading;
+                    }
+                }
+            }
+            Err(e) => {
+                debug!("{}: {:?}", e, raw_bytes);
+                counter.count(&e);
+            }
+        }
+    }
+    rejections.flush(events, event_role, &stats).await;
+    for (key, counter) in stats.iter() {
+        let who = match key.as_str() {
+            "" => transport_name.as_str(),
+            name => name,
+        };
+        info!("{} photometer {} readings: {}", label, who, counter);
     }
     if is_ref_phot {
         info!("Ref. Photometer task finished");
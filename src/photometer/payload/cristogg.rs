@@ -1,16 +1,23 @@
 // Cristobal Garcia's old way to deliver readings
 use super::super::super::Timestamp;
-use super::{Cristogg, Payload};
-use anyhow::{bail, Result};
+use super::{check_freq, Cristogg, DecodeError, Payload};
 use regex::Regex;
 use tracing::debug;
 
-const HERTZ: &str = r"^<fH([ +]\d{5})><tA ([+-]\d{4})><tO ([+-]\d{4})><mZ ([+-]\d{4})>";
-const MILLIHERTZ: &str = r"^<fm([ +]\d{5})><tA ([+-]\d{4})><tO ([+-]\d{4})><mZ ([+-]\d{4})>";
+// The frame of a reading. Its fields are checked one by one when decoding,
+// so that a corrupted field is reported instead of the whole line not matching.
+const READING: &str = r"^<f([Hm])([^>]*)><tA([^>]*)><tO([^>]*)><mZ([^>]*)>";
+
+// Signed integer field, i.e. " 04606" or "+2987"
+fn field(name: &str, text: &str) -> Result<i32, DecodeError> {
+    text.trim_start()
+        .parse::<i32>()
+        .map_err(|_| DecodeError::Malformed(format!("{} field {:?}", name, text)))
+}
 
 #[derive(Debug)]
 pub struct Decoder {
-    re: [Regex; 2],
+    re: Regex,
     sample: Option<(Timestamp, Cristogg)>, // prev sample to filter out duplicate readinngs
 }
 
@@ -23,42 +30,52 @@ impl Default for Decoder {
 impl Decoder {
     pub fn new() -> Self {
         Self {
-            re: [
-                Regex::new(HERTZ).expect("Failed pattern"),
-                Regex::new(MILLIHERTZ).expect("Failed pattern"),
-            ],
+            re: Regex::new(READING).expect("Failed pattern"),
             sample: None,
         }
     }
 
-    pub fn decode(&mut self, tstamp: Timestamp, line: &str) -> Result<(Timestamp, Payload)> {
-        if let Some(cristogg) = self.matches(line) {
-            if let Some((t, p)) = self.filter(tstamp, cristogg) {
-                return Ok((t, Payload::Cristogg(p)));
-            }
-        } else {
-            bail!("Empty Cristogg line")
-        }
-        bail!("Error decoding Cristogg payload")
+    pub fn decode(
+        &mut self,
+        tstamp: Timestamp,
+        line: &str,
+    ) -> Result<(Timestamp, Payload), DecodeError> {
+        let cristogg = self.parse(line)?;
+        check_freq(cristogg.freq)?;
+        let (t, p) = self.filter(tstamp, cristogg)?;
+        Ok((t, Payload::Cristogg(p)))
     }
 
-    pub fn matches(&self, line: &str) -> Option<Cristogg> {
-        for re in self.re.iter() {
-            if let Some(result) = re.captures(line) {
-                let cristogg = Cristogg {
-                    freq: result[1].trim().parse::<f32>().expect("Frequency") / 1000.0,
-                    zp: result[4].trim().parse::<f32>().expect("ZP"),
-                    tbox: result[2].trim().parse::<f32>().expect("Temp Box") / 100.0,
-                    tsky: result[3].trim().parse::<f32>().expect("Temp Sky") / 100.0,
-                };
-                return Some(cristogg);
-            }
-        }
-        None
+    // The line looks like a Cristogg reading, even if some field is corrupted
+    pub fn matches(&self, line: &str) -> bool {
+        self.re.is_match(line)
+    }
+
+    // <fH> carries the frequency in Hz and <fm> in mHz.
+    // Temperatures and zero point come in hundredths.
+    pub fn parse(&self, line: &str) -> Result<Cristogg, DecodeError> {
+        let Some(result) = self.re.captures(line) else {
+            return Err(DecodeError::Malformed("not a Cristogg line".to_string()));
+        };
+        let freq = field("frequency", &result[2])? as f32;
+        let freq = match &result[1] {
+            "H" => freq,
+            _ => freq / 1000.0,
+        };
+        Ok(Cristogg {
+            freq,
+            tbox: field("tA", &result[3])? as f32 / 100.0,
+            tsky: field("tO", &result[4])? as f32 / 100.0,
+            zp: field("mZ", &result[5])? as f32 / 100.0,
+        })
     }
 
     // Filter duplicated readings
-    fn filter(&mut self, tstamp: Timestamp, reading: Cristogg) -> Option<(Timestamp, Cristogg)> {
+    fn filter(
+        &mut self,
+        tstamp: Timestamp,
+        reading: Cristogg,
+    ) -> Result<(Timestamp, Cristogg), DecodeError> {
         let cur_sample = (tstamp, reading);
         if let Some(prev_sample) = &self.sample {
             if prev_sample.1.freq == cur_sample.1.freq
@@ -68,15 +85,15 @@ impl Decoder {
                 // duplicate reading, update and signal we have nothing
                 debug!("Discarding duplicate Cristogg reading {cur_sample:?}");
                 self.sample = Some(cur_sample);
-                None
+                Err(DecodeError::Duplicate)
             } else {
                 let result = self.sample.clone(); // Can't apply move semantics behind mut self
                 self.sample = Some(cur_sample);
-                result
+                result.ok_or(DecodeError::Bootstrap)
             }
         } else {
             self.sample = Some(cur_sample); // bootstrapping the filter for the first time
-            None
+            Err(DecodeError::Bootstrap)
         }
     }
 }
@@ -1,12 +1,95 @@
 // JSON parsing stuff
 use super::super::super::Timestamp;
-use super::{Json, Payload};
-use anyhow::{bail, Result};
+use super::{check_freq, DecodeError, Json, Payload, Sequence};
 use serde_json;
-use tracing::debug;
+use tracing::{debug, info, warn};
+
+// Fields each firmware revision is expected to send, newest last.
+// Unknown revisions are checked against the closest older one.
+pub const REVISIONS: [(i8, &[&str]); 2] = [
+    (
+        1,
+        &["udp", "rev", "name", "freq", "mag", "tamb", "tsky", "ZP"],
+    ),
+    (
+        2,
+        &[
+            "udp", "rev", "name", "freq", "mag", "tamb", "tsky", "wdBm", "ain", "ZP",
+        ],
+    ),
+];
+
+fn expected_fields(rev: Option<i8>) -> &'static [&'static str] {
+    let rev = rev.unwrap_or(REVISIONS[0].0);
+    REVISIONS
+        .iter()
+        .rev()
+        .find(|(r, _)| *r <= rev)
+        .unwrap_or(&REVISIONS[0])
+        .1
+}
+
+fn is_present(reading: &Json, field: &str) -> bool {
+    match field {
+        "udp" => reading.udp.is_some(),
+        "rev" => reading.rev.is_some(),
+        "mag" => reading.mag.is_some(),
+        "tamb" => reading.tamb.is_some(),
+        "tsky" => reading.tsky.is_some(),
+        "wdBm" => reading.wdBm.is_some(),
+        "ain" => reading.ain.is_some(),
+        "ZP" => reading.ZP.is_some(),
+        _ => true, // name and freq can't be missing
+    }
+}
+
+// A udp counter this far behind the previous one is a late datagram,
+// further behind or this close to zero the photometer has restarted counting
+const REORDER_WINDOW: u32 = 10;
+
+fn is_reboot(prev: u32, cur: u32) -> bool {
+    cur < REORDER_WINDOW || prev - cur > REORDER_WINDOW
+}
+
+// Follows the udp counter of a photometer, telling duplicates
+// and late readings apart from gaps and reboots
+#[derive(Debug, Default)]
+pub struct Counter {
+    udp: Option<u32>,           // newest counter seen
+    sequence: Option<Sequence>, // what the last counter told, until taken
+}
+
+impl Counter {
+    pub fn track(&mut self, cur: u32) -> Result<(), DecodeError> {
+        let Some(prev) = self.udp else {
+            self.udp = Some(cur);
+            return Ok(());
+        };
+        if cur == prev {
+            return Err(DecodeError::Duplicate);
+        }
+        if cur < prev && !is_reboot(prev, cur) {
+            return Err(DecodeError::OutOfOrder); // keep counting from the newest one
+        }
+        if cur < prev {
+            self.sequence = Some(Sequence::Reboot);
+        } else if cur.wrapping_sub(prev) > 1 {
+            self.sequence = Some(Sequence::Lost(cur.wrapping_sub(prev) - 1));
+        }
+        self.udp = Some(cur);
+        Ok(())
+    }
+
+    // Readings lost or photometer rebooted, as told by the last counter
+    pub fn take_sequence(&mut self) -> Option<Sequence> {
+        self.sequence.take()
+    }
+}
 
 pub struct Decoder {
     sample: Option<(Timestamp, Json)>, // prev sample to filter out duplicate readinngs
+    rev: Option<Option<i8>>,           // revision already checked, to warn only once
+    counter: Counter,
 }
 
 // Ok((tstamp, Payload::Json(info)))
@@ -19,37 +102,94 @@ impl Default for Decoder {
 
 impl Decoder {
     pub fn new() -> Self {
-        Self { sample: None }
+        Self {
+            sample: None,
+            rev: None,
+            counter: Counter::default(),
+        }
     }
 
-    pub fn decode(&mut self, tstamp: Timestamp, line: &str) -> Result<(Timestamp, Payload)> {
-        if let Ok(info) = serde_json::from_str(line) {
-            if let Some((t, p)) = self.filter(tstamp, info) {
-                Ok((t, Payload::Json(p)))
-            } else {
-                bail!("duplicate JSON payload")
-            }
-        } else {
-            bail!("invalid JSON Decodification")
+    pub fn decode(
+        &mut self,
+        tstamp: Timestamp,
+        line: &str,
+    ) -> Result<(Timestamp, Payload), DecodeError> {
+        let info = serde_json::from_str::<Json>(line)
+            .map_err(|e| DecodeError::Malformed(e.to_string()))?;
+        self.check_revision(&info);
+        check_freq(info.freq)?;
+        let (t, p) = self.filter(tstamp, info)?;
+        Ok((t, Payload::Json(p)))
+    }
+
+    // Tells once per firmware revision what the photometer sends
+    // beyond or short of what we know about that revision
+    fn check_revision(&mut self, reading: &Json) {
+        if self.rev == Some(reading.rev) {
+            return;
+        }
+        self.rev = Some(reading.rev);
+        let newest = REVISIONS[REVISIONS.len() - 1].0;
+        match reading.rev {
+            None => warn!("{} sends no payload revision", reading.name),
+            Some(rev) if rev > newest => warn!(
+                "{} sends payload revision {}, newer than {} we know of",
+                reading.name, rev, newest
+            ),
+            Some(rev) => debug!("{} sends payload revision {}", reading.name, rev),
+        }
+        let missing = expected_fields(reading.rev)
+            .iter()
+            .filter(|field| !is_present(reading, field))
+            .copied()
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            warn!(
+                "{} readings lack field(s) {}, calibrating without them",
+                reading.name,
+                missing.join(", ")
+            );
+        }
+        if !reading.extra.is_empty() {
+            let unknown = reading.extra.keys().cloned().collect::<Vec<_>>();
+            info!(
+                "{} readings carry unknown field(s) {}, kept as sent",
+                reading.name,
+                unknown.join(", ")
+            );
         }
     }
 
-    // Filter duplicated readings
-    fn filter(&mut self, tstamp: Timestamp, reading: Json) -> Option<(Timestamp, Json)> {
+    // Readings lost or photometer rebooted, as told by the last udp counter
+    pub fn take_sequence(&mut self) -> Option<Sequence> {
+        self.counter.take_sequence()
+    }
+
+    // Filter duplicated and out of order readings, noting gaps and reboots on the way
+    fn filter(
+        &mut self,
+        tstamp: Timestamp,
+        reading: Json,
+    ) -> Result<(Timestamp, Json), DecodeError> {
         let cur_sample = (tstamp, reading);
-        if let Some(prev_sample) = &self.sample {
-            if prev_sample.1.udp != cur_sample.1.udp {
-                let result = self.sample.clone(); // Can't apply move semantics behind mut self
-                self.sample = Some(cur_sample);
-                result
-            } else {
-                debug!("Discarding duplicate JSON reading {cur_sample:?}");
-                self.sample = Some(cur_sample); // duplicate reading
-                None
+        // Without sequence numbers there is no telling duplicates apart
+        if let Some(cur) = cur_sample.1.udp {
+            match self.counter.track(cur) {
+                Err(DecodeError::Duplicate) => {
+                    debug!("Discarding duplicate JSON reading {cur_sample:?}");
+                    self.sample = Some(cur_sample); // duplicate reading
+                    return Err(DecodeError::Duplicate);
+                }
+                Err(e) => {
+                    debug!("Discarding out of order JSON reading {cur_sample:?}");
+                    return Err(e);
+                }
+                Ok(()) => (),
             }
-        } else {
-            self.sample = Some(cur_sample); // bootstrapping the filter for the first time
-            None
+        }
+        match self.sample.replace(cur_sample) {
+            Some(prev_sample) => Ok(prev_sample),
+            None => Err(DecodeError::Bootstrap), // bootstrapping the filter for the first time
         }
     }
 }
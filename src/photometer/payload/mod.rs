@@ -1,24 +1,32 @@
 pub mod cristogg;
 pub mod json;
+pub mod tess4c;
 use super::super::Timestamp;
-use anyhow::Result;
+use super::profile::Format;
 use serde::Deserialize;
+use std::fmt;
 
 // --
-// This is the decoded, new JSON format payload
+// This is the decoded, new JSON format payload.
+// Only name and freq are needed to calibrate. The fields a firmware
+// revision should send are checked by the JSON decoder, see json::REVISIONS.
 #[derive(Deserialize, Clone, Debug)]
 #[allow(non_snake_case)]
 pub struct Json {
-    pub udp: u32,
-    pub rev: i8,
+    pub udp: Option<u32>,
+    pub rev: Option<i8>,
     pub name: String,
     pub freq: f32,
-    pub mag: f32,
-    pub tamb: f32,
-    pub tsky: f32,
-    pub wdBm: i16,
-    pub ain: i16,
-    pub ZP: f32,
+    pub mag: Option<f32>,
+    pub tamb: Option<f32>,
+    pub tsky: Option<f32>,
+    pub wdBm: Option<i16>,
+    pub ain: Option<i16>,
+    pub ZP: Option<f32>,
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, serde_json::Value>, // fields unknown to us, kept as sent
+    #[serde(skip)]
+    pub channel: Option<u8>, // TESS4C channel, from 1 to 4
 }
 
 // ---------------------------------------------------
@@ -41,16 +49,200 @@ pub enum Payload {
     Cristogg(Cristogg),
 }
 
+impl Payload {
+    // The format this payload was decoded from
+    pub fn format(&self) -> Format {
+        match self {
+            Payload::Json(json) if json.channel.is_some() => Format::Tess4c,
+            Payload::Json(_) => Format::Json,
+            Payload::Cristogg(_) => Format::Cristogg,
+        }
+    }
+}
+
+// Why a line yields no samples
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    Malformed(String),  // not the expected format, with the reason
+    Duplicate,          // same reading sent again
+    Bootstrap,          // first reading, only used to filter out duplicates
+    OutOfRange(String), // decoded, but with impossible values
+    OutOfOrder,         // sent before the previous reading
+    UnknownFormat,      // no decoder recognizes the line
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Malformed(reason) => write!(f, "malformed payload: {}", reason),
+            DecodeError::Duplicate => write!(f, "duplicate payload"),
+            DecodeError::Bootstrap => write!(f, "first payload, kept to filter duplicates"),
+            DecodeError::OutOfRange(reason) => write!(f, "payload out of range: {}", reason),
+            DecodeError::OutOfOrder => write!(f, "out of order payload"),
+            DecodeError::UnknownFormat => write!(f, "unknown payload format"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+// What a sequence counter tells about the readings before the current one
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sequence {
+    Lost(u32), // readings never received
+    Reboot,    // the photometer restarted counting
+}
+
+// Frequencies are never negative, whatever the format
+fn check_freq(freq: f32) -> Result<(), DecodeError> {
+    if freq.is_finite() && freq >= 0.0 {
+        Ok(())
+    } else {
+        Err(DecodeError::OutOfRange(format!("freq = {}", freq)))
+    }
+}
+
+// Running count of the lines received from a photometer, by outcome
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DecodeStats {
+    pub accepted: u32,
+    pub malformed: u32,
+    pub duplicate: u32,
+    pub bootstrap: u32,
+    pub out_of_range: u32,
+    pub out_of_order: u32,
+    pub unknown_format: u32,
+    pub lost: u32, // readings never received, by their sequence counter
+    pub reboots: u32,
+}
+
+impl DecodeStats {
+    pub fn count(&mut self, error: &DecodeError) {
+        match error {
+            DecodeError::Malformed(_) => self.malformed += 1,
+            DecodeError::Duplicate => self.duplicate += 1,
+            DecodeError::Bootstrap => self.bootstrap += 1,
+            DecodeError::OutOfRange(_) => self.out_of_range += 1,
+            DecodeError::OutOfOrder => self.out_of_order += 1,
+            DecodeError::UnknownFormat => self.unknown_format += 1,
+        }
+    }
+
+    pub fn sequence(&mut self, sequence: Sequence) {
+        match sequence {
+            Sequence::Lost(n) => self.lost += n,
+            Sequence::Reboot => self.reboots += 1,
+        }
+    }
+
+    // Takes the rejected line counts of a newer report
+    pub fn rejections(&mut self, other: &DecodeStats) {
+        self.malformed = other.malformed;
+        self.duplicate = other.duplicate;
+        self.bootstrap = other.bootstrap;
+        self.out_of_range = other.out_of_range;
+        self.out_of_order = other.out_of_order;
+        self.unknown_format = other.unknown_format;
+    }
+
+    pub fn rejected(&self) -> u32 {
+        self.malformed
+            + self.duplicate
+            + self.bootstrap
+            + self.out_of_range
+            + self.out_of_order
+            + self.unknown_format
+    }
+
+    // Percentage of the readings sent that never arrived
+    pub fn loss(&self) -> f32 {
+        let sent = self.accepted + self.lost;
+        if sent == 0 {
+            0.0
+        } else {
+            100.0 * self.lost as f32 / sent as f32
+        }
+    }
+
+    pub fn add(&mut self, other: &DecodeStats) {
+        self.accepted += other.accepted;
+        self.malformed += other.malformed;
+        self.duplicate += other.duplicate;
+        self.bootstrap += other.bootstrap;
+        self.out_of_range += other.out_of_range;
+        self.out_of_order += other.out_of_order;
+        self.unknown_format += other.unknown_format;
+        self.lost += other.lost;
+        self.reboots += other.reboots;
+    }
+}
+
+impl fmt::Display for DecodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} accepted, {} rejected ({} malformed, {} duplicate, {} bootstrap, {} out of range, {} out of order, {} unknown), {} lost, {} reboots",
+            self.accepted,
+            self.rejected(),
+            self.malformed,
+            self.duplicate,
+            self.bootstrap,
+            self.out_of_range,
+            self.out_of_order,
+            self.unknown_format,
+            self.lost,
+            self.reboots
+        )
+    }
+}
+
 pub enum Decoder {
     Json(json::Decoder),
     Cristogg(cristogg::Decoder),
+    Tess4c(tess4c::Decoder),
 }
 
 impl Decoder {
-    pub fn decode(&mut self, tstamp: Timestamp, line: &str) -> Result<(Timestamp, Payload)> {
+    // Returns a decoder for the payload format recognized in a line, if any
+    pub fn sniff(line: &str) -> Option<Decoder> {
+        if tess4c::Decoder::new().matches(line) {
+            Some(Decoder::Tess4c(tess4c::Decoder::new()))
+        } else if serde_json::from_str::<Json>(line).is_ok() {
+            Some(Decoder::Json(json::Decoder::new()))
+        } else if cristogg::Decoder::new().matches(line) {
+            Some(Decoder::Cristogg(cristogg::Decoder::new()))
+        } else {
+            None
+        }
+    }
+
+    pub fn format(&self) -> Format {
+        match self {
+            Decoder::Json(_) => Format::Json,
+            Decoder::Cristogg(_) => Format::Cristogg,
+            Decoder::Tess4c(_) => Format::Tess4c,
+        }
+    }
+
+    // Only JSON readings carry a sequence counter that is followed
+    pub fn take_sequence(&mut self) -> Option<Sequence> {
+        match self {
+            Decoder::Json(p) => p.take_sequence(),
+            Decoder::Tess4c(p) => p.take_sequence(),
+            Decoder::Cristogg(_) => None,
+        }
+    }
+
+    // Multi-channel photometers yield one payload per channel
+    pub fn decode(
+        &mut self,
+        tstamp: Timestamp,
+        line: &str,
+    ) -> Result<Vec<(Timestamp, Payload)>, DecodeError> {
         match self {
-            Decoder::Cristogg(p) => p.decode(tstamp, line),
-            Decoder::Json(p) => p.decode(tstamp, line),
+            Decoder::Cristogg(p) => Ok(vec![p.decode(tstamp, line)?]),
+            Decoder::Json(p) => Ok(vec![p.decode(tstamp, line)?]),
+            Decoder::Tess4c(p) => p.decode(tstamp, line),
         }
     }
 }
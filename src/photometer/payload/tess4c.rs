@@ -0,0 +1,119 @@
+// TESS4C four channel JSON readings, split into one JSON payload per channel
+use super::super::super::Timestamp;
+use super::json::Counter;
+use super::{check_freq, DecodeError, Json, Payload, Sequence};
+use serde::Deserialize;
+use serde_json;
+use tracing::debug;
+
+pub const CHANNELS: usize = 4;
+
+// Only the name and the frequencies are needed to calibrate, as for single channel readings
+#[derive(Deserialize, Clone, Debug)]
+#[allow(non_snake_case)]
+struct Reading {
+    udp: Option<u32>,
+    rev: Option<i8>,
+    name: String,
+    freq1: Option<f32>,
+    mag1: Option<f32>,
+    ZP1: Option<f32>,
+    freq2: Option<f32>,
+    mag2: Option<f32>,
+    ZP2: Option<f32>,
+    freq3: Option<f32>,
+    mag3: Option<f32>,
+    ZP3: Option<f32>,
+    freq4: Option<f32>,
+    mag4: Option<f32>,
+    ZP4: Option<f32>,
+    tamb: Option<f32>,
+    tsky: Option<f32>,
+    wdBm: Option<i16>,
+    ain: Option<i16>,
+}
+
+type Channel = (Option<f32>, Option<f32>, Option<f32>); // freq, mag and ZP
+
+impl Reading {
+    fn channels(&self) -> [Channel; CHANNELS] {
+        [
+            (self.freq1, self.mag1, self.ZP1),
+            (self.freq2, self.mag2, self.ZP2),
+            (self.freq3, self.mag3, self.ZP3),
+            (self.freq4, self.mag4, self.ZP4),
+        ]
+    }
+
+    // Channels without a frequency are left out
+    fn split(&self) -> Vec<Json> {
+        self.channels()
+            .iter()
+            .enumerate()
+            .filter_map(|(i, &(freq, mag, zp))| {
+                Some(Json {
+                    udp: self.udp,
+                    rev: self.rev,
+                    name: self.name.clone(),
+                    freq: freq?,
+                    mag,
+                    tamb: self.tamb,
+                    tsky: self.tsky,
+                    wdBm: self.wdBm,
+                    ain: self.ain,
+                    ZP: zp,
+                    extra: serde_json::Map::new(),
+                    channel: Some(i as u8 + 1),
+                })
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+pub struct Decoder {
+    counter: Counter, // the same udp counter as single channel readings
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Told apart from single channel readings by its channel frequencies
+    pub fn matches(&self, line: &str) -> bool {
+        serde_json::from_str::<Reading>(line)
+            .is_ok_and(|reading| reading.channels().iter().any(|(freq, _, _)| freq.is_some()))
+    }
+
+    // Readings lost or photometer rebooted, as told by the last udp counter
+    pub fn take_sequence(&mut self) -> Option<Sequence> {
+        self.counter.take_sequence()
+    }
+
+    pub fn decode(
+        &mut self,
+        tstamp: Timestamp,
+        line: &str,
+    ) -> Result<Vec<(Timestamp, Payload)>, DecodeError> {
+        let reading = serde_json::from_str::<Reading>(line)
+            .map_err(|e| DecodeError::Malformed(e.to_string()))?;
+        let payloads = reading.split();
+        if payloads.is_empty() {
+            return Err(DecodeError::Malformed("no channel frequency".into()));
+        }
+        for json in payloads.iter() {
+            check_freq(json.freq)?;
+        }
+        if let Some(udp) = reading.udp {
+            if let Err(e) = self.counter.track(udp) {
+                debug!("Discarding TESS4C reading {reading:?}: {e}");
+                return Err(e);
+            }
+        }
+        Ok(payloads
+            .into_iter()
+            .map(|json| (tstamp, Payload::Json(json)))
+            .collect())
+    }
+}
@@ -0,0 +1,135 @@
+// What each photometer model speaks, after the model table in NOTES.md.
+// Supporting a new model means adding its profile here and to MODELS.
+
+use super::transport::Endpoint;
+use super::Model;
+use std::fmt;
+
+// Where readings come from by default
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Readings {
+    Udp,
+    Serial,
+}
+
+// Payload format of the readings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Cristogg,
+    Tess4c, // four channels JSON
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Format::Json => write!(f, "JSON"),
+            Format::Cristogg => write!(f, "Cristogg"),
+            Format::Tess4c => write!(f, "TESS4C"),
+        }
+    }
+}
+
+// How the photometer info is discovered and its zero point written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Link {
+    Http,     // web page served by the photometer access point
+    Serial,   // command/response protocol on the readings serial port
+    Database, // ref-device section in config_t, never written
+}
+
+#[derive(Debug)]
+pub struct Profile {
+    pub model: &'static str, // as stored in the database
+    pub cli: &'static str,   // as given in the command line
+    pub about: &'static str, // command line help
+    pub sensor: &'static str,
+    pub readings: Readings,
+    pub format: Format,
+    pub channels: usize, // each one calibrated on its own
+    pub info: Link,
+    pub writer: Link,
+}
+
+impl Profile {
+    pub fn default_endpoint(&self) -> Endpoint {
+        match self.readings {
+            Readings::Udp => Endpoint::default_udp(),
+            Readings::Serial => Endpoint::default_serial(),
+        }
+    }
+}
+
+// The reference photometer is a TESS-W with its readings on the serial line
+pub const REFERENCE: Profile = Profile {
+    model: "TESS-W",
+    cli: "tess-w",
+    about: "Reference TESS WiFi model",
+    sensor: "TSL237",
+    readings: Readings::Serial,
+    format: Format::Cristogg,
+    channels: 1,
+    info: Link::Database,
+    writer: Link::Database,
+};
+
+pub const TESSW: Profile = Profile {
+    model: "TESS-W",
+    cli: "tess-w",
+    about: "TESS WiFi model",
+    sensor: "TSL237",
+    readings: Readings::Udp,
+    format: Format::Json,
+    channels: 1,
+    info: Link::Http,
+    writer: Link::Http,
+};
+
+pub const TESSP: Profile = Profile {
+    model: "TESS-P",
+    cli: "tess-p",
+    about: "TESS Portable model",
+    sensor: "TSL237",
+    readings: Readings::Serial,
+    format: Format::Json,
+    channels: 1,
+    info: Link::Serial,
+    writer: Link::Serial,
+};
+
+pub const TAS: Profile = Profile {
+    model: "TAS",
+    cli: "tas",
+    about: "TESS Auto Scan model",
+    sensor: "TSL237",
+    readings: Readings::Serial,
+    format: Format::Json,
+    channels: 1,
+    info: Link::Serial,
+    writer: Link::Serial,
+};
+
+pub const TESS4C: Profile = Profile {
+    model: "TESS4C",
+    cli: "tess4c",
+    about: "TESS four channels model",
+    sensor: "TSL237",
+    readings: Readings::Udp,
+    format: Format::Tess4c,
+    channels: 4,
+    info: Link::Http,
+    writer: Link::Http,
+};
+
+// Test photometer models, as offered in the command line
+pub static MODELS: [&Profile; 4] = [&TESSW, &TESSP, &TAS, &TESS4C];
+
+impl Model {
+    pub fn all() -> impl Iterator<Item = Model> {
+        MODELS.iter().map(|profile| Model(profile))
+    }
+
+    pub fn profile(&self) -> &'static Profile {
+        self.0
+    }
+}
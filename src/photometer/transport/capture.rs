@@ -0,0 +1,81 @@
+// Raw photometer traffic capture, stored as JSON Lines
+
+use super::RawSample;
+use anyhow::{Context, Result};
+use chrono::SecondsFormat;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Record {
+    pub tstamp: String, // arrival timestamp, RFC 3339 with milliseconds
+    pub role: String,   // either 'ref' or 'test'
+    pub transport: String,
+    pub line: String,
+}
+
+// Handle given to each photometer reading task.
+// Recording never blocks the photometer reading loop.
+#[derive(Clone, Debug)]
+pub struct Capture {
+    channel: UnboundedSender<Record>,
+}
+
+impl Capture {
+    pub fn new(channel: UnboundedSender<Record>) -> Self {
+        Self { channel }
+    }
+
+    pub fn record(&self, role: &str, transport: &str, sample: &RawSample) {
+        let RawSample(tstamp, line) = sample;
+        let record = Record {
+            tstamp: tstamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+            role: role.to_string(),
+            transport: transport.to_string(),
+            line: line.clone(),
+        };
+        // The capture task may have died, but that must not stop readings
+        let _ = self.channel.send(record);
+    }
+}
+
+// Creates the capture file before any reading, so that a bad path is reported at once.
+// Returns the handle for the reading tasks and the capture task, to be awaited
+// once the reading tasks are over.
+pub async fn start(path: PathBuf) -> Result<(Capture, JoinHandle<Result<()>>)> {
+    let file = File::create(&path)
+        .await
+        .with_context(|| format!("Creating capture file {}", path.display()))?;
+    let (tx, rx) = mpsc::unbounded_channel::<Record>();
+    let task = tokio::spawn(capture_task(path, file, rx));
+    Ok((Capture::new(tx), task))
+}
+
+// Writes every record received to the capture file until all handles are dropped
+async fn capture_task(
+    path: PathBuf,
+    mut file: File,
+    mut chan: UnboundedReceiver<Record>,
+) -> Result<()> {
+    info!("Capturing raw photometer traffic to {}", path.display());
+    while let Some(record) = chan.recv().await {
+        let mut line = serde_json::to_string(&record)?;
+        line.push('\n');
+        // Flushed line by line, so that nothing is lost on Ctrl-C
+        let written = match file.write_all(line.as_bytes()).await {
+            Ok(_) => file.flush().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            error!("Writing to capture file {}: {}", path.display(), e);
+            return Err(e.into());
+        }
+    }
+    info!("Capture task finished");
+    Ok(())
+}
@@ -1,21 +1,213 @@
+pub mod capture;
+pub mod mqtt;
+pub mod replay;
 pub mod serial;
+pub mod simulator;
+pub mod tcp;
 pub mod udp;
 
 use super::super::Timestamp;
+use anyhow::{anyhow, bail};
+use std::fmt;
 use std::io::Error;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub const DEFAULT_UDP_PORT: u16 = 2255;
+const ANY_ADDR: &str = "0.0.0.0";
 
 pub struct RawSample(pub Timestamp, pub String);
 
+// Where to listen or connect to a photometer:
+// serial:<device>[:<baud>], udp:[<bind address>:]<port> or tcp:<host>:<port>
+// udp:[<bind address>:]<port>[,from=<ip>][,name=<name>] listens to a single photometer
+// serial:auto[:<baud>] probes all serial ports looking for a photometer
+// mqtt:<broker host>[:<port>]/<topic> subscribes to readings published by field photometers
+// replay:<capture file>[:fast] feeds back captured traffic
+// sim:<json|cristogg>[,<key>=<value>...] generates synthetic readings
+#[derive(Debug, Clone, PartialEq)]
+pub enum Endpoint {
+    Serial(serial::Port),
+    Udp(String, u16, udp::Filter),
+    Tcp(String, u16),
+    Mqtt(String, u16, String),
+    Replay(PathBuf, bool),
+    Simulator(simulator::Params),
+}
+
+impl Endpoint {
+    pub fn default_serial() -> Self {
+        Endpoint::Serial(serial::Port::default())
+    }
+
+    pub fn default_udp() -> Self {
+        Endpoint::Udp(
+            ANY_ADDR.to_string(),
+            DEFAULT_UDP_PORT,
+            udp::Filter::default(),
+        )
+    }
+}
+
+fn parse_udp(rest: &str, endpoint: &str) -> anyhow::Result<Endpoint> {
+    let mut items = rest.split(',');
+    let address = items.next().unwrap_or_default();
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host.to_string(), parse_port(port, endpoint)?),
+        None => (ANY_ADDR.to_string(), parse_port(address, endpoint)?),
+    };
+    let mut filter = udp::Filter::default();
+    for item in items {
+        match item.split_once('=') {
+            Some(("from", ip)) => {
+                filter.from =
+                    Some(ip.parse().map_err(|_| {
+                        anyhow!("Invalid IP address {} in endpoint {}", ip, endpoint)
+                    })?)
+            }
+            Some(("name", name)) if !name.is_empty() => filter.name = Some(name.to_string()),
+            _ => bail!("UDP option must be from=<ip> or name=<name>: {}", item),
+        }
+    }
+    Ok(Endpoint::Udp(host, port, filter))
+}
+
+fn parse_port(s: &str, endpoint: &str) -> anyhow::Result<u16> {
+    s.parse::<u16>()
+        .map_err(|_| anyhow!("Invalid port number in endpoint {}", endpoint))
+}
+
+impl FromStr for Endpoint {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((scheme, rest)) = s.split_once(':') else {
+            bail!("Missing scheme in endpoint {}", s);
+        };
+        match scheme {
+            "serial" => {
+                // The baud rate is optional and device names may contain colons
+                let port = match rest.rsplit_once(':') {
+                    Some((device, baud)) if baud.parse::<u32>().is_ok() => serial::Port {
+                        device: device.to_string(),
+                        baud: baud.parse::<u32>()?,
+                    },
+                    _ => serial::Port {
+                        device: rest.to_string(),
+                        baud: serial::DEFAULT_BAUD,
+                    },
+                };
+                if port.device.is_empty() {
+                    bail!("Missing serial device in endpoint {}", s);
+                }
+                Ok(Endpoint::Serial(port))
+            }
+            "udp" => parse_udp(rest, s),
+            "tcp" => match rest.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() => {
+                    Ok(Endpoint::Tcp(host.to_string(), parse_port(port, s)?))
+                }
+                _ => bail!("Missing host or port in endpoint {}", s),
+            },
+            "mqtt" => {
+                let Some((broker, topic)) = rest.split_once('/') else {
+                    bail!("Missing topic in endpoint {}", s);
+                };
+                let (host, port) = match broker.rsplit_once(':') {
+                    Some((host, port)) => (host, parse_port(port, s)?),
+                    None => (broker, mqtt::DEFAULT_MQTT_PORT),
+                };
+                if host.is_empty() || topic.is_empty() {
+                    bail!("Missing broker host or topic in endpoint {}", s);
+                }
+                Ok(Endpoint::Mqtt(host.to_string(), port, topic.to_string()))
+            }
+            "replay" => {
+                let (path, fast) = match rest.strip_suffix(":fast") {
+                    Some(path) => (path, true),
+                    None => (rest, false),
+                };
+                if path.is_empty() {
+                    bail!("Missing capture file in endpoint {}", s);
+                }
+                Ok(Endpoint::Replay(PathBuf::from(path), fast))
+            }
+            "sim" => Ok(Endpoint::Simulator(rest.parse::<simulator::Params>()?)),
+            _ => bail!("Unsupported scheme {} in endpoint {}", scheme, s),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Serial(port) => write!(f, "serial:{}:{}", port.device, port.baud),
+            Endpoint::Udp(host, port, filter) => write!(f, "udp:{}:{}{}", host, port, filter),
+            Endpoint::Tcp(host, port) => write!(f, "tcp:{}:{}", host, port),
+            Endpoint::Mqtt(host, port, topic) => write!(f, "mqtt:{}:{}/{}", host, port, topic),
+            Endpoint::Replay(path, false) => write!(f, "replay:{}", path.display()),
+            Endpoint::Replay(path, true) => write!(f, "replay:{}:fast", path.display()),
+            Endpoint::Simulator(params) => write!(f, "sim:{}", params),
+        }
+    }
+}
+
 pub enum Transport {
     Serial(serial::Transport),
     Udp(udp::Transport),
+    Tcp(tcp::Transport),
+    Mqtt(Box<mqtt::Transport>),
+    Replay(Box<replay::Transport>),
+    Simulator(simulator::Transport),
 }
 
 impl Transport {
+    // The role selects which records are fed back when replaying a capture file
+    pub async fn open(endpoint: &Endpoint, role: &str) -> Result<Self, Error> {
+        match endpoint {
+            Endpoint::Serial(port) => Ok(Transport::Serial(serial::Transport::new(port).await?)),
+            Endpoint::Udp(host, port, filter) => Ok(Transport::Udp(
+                udp::Transport::new(host, *port, filter).await?,
+            )),
+            Endpoint::Tcp(host, port) => {
+                Ok(Transport::Tcp(tcp::Transport::new(host, *port).await?))
+            }
+            Endpoint::Mqtt(host, port, topic) => Ok(Transport::Mqtt(Box::new(
+                mqtt::Transport::new(host, *port, topic, role).await?,
+            ))),
+            Endpoint::Replay(path, fast) => Ok(Transport::Replay(Box::new(
+                replay::Transport::new(path, role, *fast).await?,
+            ))),
+            Endpoint::Simulator(params) => {
+                Ok(Transport::Simulator(simulator::Transport::new(params)))
+            }
+        }
+    }
+
+    // Transports backed by a device or socket that can be reopened after a failure
+    pub fn is_reconnectable(&self) -> bool {
+        matches!(
+            self,
+            Transport::Serial(_) | Transport::Udp(_) | Transport::Tcp(_) | Transport::Mqtt(_)
+        )
+    }
+
+    // Only serial photometers accept commands
+    pub fn commander(&self) -> Option<serial::Commander> {
+        match self {
+            Transport::Serial(t) => Some(t.commander()),
+            _ => None,
+        }
+    }
+
     pub async fn reading(&mut self) -> Result<RawSample, Error> {
         match self {
             Transport::Serial(t) => t.reading().await,
             Transport::Udp(t) => t.reading().await,
+            Transport::Tcp(t) => t.reading().await,
+            Transport::Mqtt(t) => t.reading().await,
+            Transport::Replay(t) => t.reading().await,
+            Transport::Simulator(t) => t.reading().await,
         }
     }
 }
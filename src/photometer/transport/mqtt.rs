@@ -0,0 +1,67 @@
+// Subscriber to the readings that field photometers publish to an MQTT broker
+
+use super::RawSample;
+use chrono::prelude::*;
+use rumqttc::{AsyncClient, ConnectionError, Event, EventLoop, MqttOptions, Packet, QoS};
+use std::io;
+use std::process;
+use tokio::time::Duration;
+use tracing::info;
+
+pub const DEFAULT_MQTT_PORT: u16 = 1883;
+const KEEP_ALIVE: u64 = 60; // seconds
+const REQUESTS_CAPACITY: usize = 10;
+
+fn to_io_error(e: ConnectionError) -> io::Error {
+    match e {
+        ConnectionError::Io(e) => e,
+        e => io::Error::other(e),
+    }
+}
+
+pub struct Transport {
+    _client: AsyncClient, // the event loop stops when the client is dropped
+    eventloop: EventLoop,
+}
+
+impl Transport {
+    // Waits for the broker to accept the connection so that a wrong
+    // broker address is reported when opening, not when reading.
+    // The client id tells the role apart, as brokers drop a client when another
+    // one connects with the same id.
+    pub async fn new(host: &str, port: u16, topic: &str, role: &str) -> Result<Self, io::Error> {
+        let client_id = format!("zptess-{}-{}", role, process::id());
+        let mut options = MqttOptions::new(client_id, host, port);
+        options.set_keep_alive(Duration::from_secs(KEEP_ALIVE));
+        let (client, mut eventloop) = AsyncClient::new(options, REQUESTS_CAPACITY);
+        client
+            .subscribe(topic, QoS::AtMostOnce)
+            .await
+            .map_err(io::Error::other)?;
+        loop {
+            if let Event::Incoming(Packet::ConnAck(_)) =
+                eventloop.poll().await.map_err(to_io_error)?
+            {
+                break;
+            }
+        }
+        info!("Subscribed to {} on MQTT broker {}:{}", topic, host, port);
+        Ok(Self {
+            _client: client,
+            eventloop,
+        })
+    }
+
+    pub async fn reading(&mut self) -> Result<RawSample, io::Error> {
+        loop {
+            match self.eventloop.poll().await.map_err(to_io_error)? {
+                Event::Incoming(Packet::Publish(message)) => {
+                    let tstamp = Utc::now();
+                    let s = String::from_utf8_lossy(&message.payload).trim().to_string();
+                    return Ok(RawSample(tstamp, s));
+                }
+                _ => continue,
+            }
+        }
+    }
+}
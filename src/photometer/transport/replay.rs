@@ -0,0 +1,190 @@
+// Feeds back raw photometer traffic from a capture file
+
+use super::capture::Record;
+use super::RawSample;
+use crate::Timestamp;
+use chrono::DateTime;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::io::{Error, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
+use tokio::fs::File;
+use tokio::io::{AsyncBufReadExt, BufReader, Lines};
+use tokio::sync::Notify;
+use tokio::time::{sleep_until, Instant};
+use tracing::warn;
+
+// Records read ahead for a role before the other roles wait for it to catch up
+const MAX_LAG: usize = 32;
+
+type Line = (Timestamp, String);
+type Players = HashMap<(PathBuf, bool), Arc<Player>>; // by capture file and pace
+
+// Capture files being replayed, so that the ref and test photometers
+// are fed back from a single reader, in the order they were captured
+static PLAYERS: OnceLock<Mutex<Players>> = OnceLock::new();
+
+struct Reader {
+    lines: Lines<BufReader<File>>,
+    origin: Option<(Timestamp, Instant)>, // first record timestamp and its replay time
+    queues: HashMap<String, VecDeque<Line>>, // records read ahead, by role
+}
+
+impl Reader {
+    async fn next_record(&mut self) -> Result<(Timestamp, Record), io::Error> {
+        while let Some(line) = self.lines.next_line().await? {
+            let record = match serde_json::from_str::<Record>(&line) {
+                Ok(record) => record,
+                Err(e) => {
+                    warn!("Skipping malformed capture record: {}", e);
+                    continue;
+                }
+            };
+            match DateTime::parse_from_rfc3339(&record.tstamp) {
+                Ok(tstamp) => return Ok((tstamp.into(), record)),
+                Err(e) => warn!("Skipping capture record with bad timestamp: {}", e),
+            }
+        }
+        Err(Error::new(ErrorKind::UnexpectedEof, "End of capture file"))
+    }
+}
+
+struct Player {
+    key: (PathBuf, bool),
+    reader: tokio::sync::Mutex<Reader>,
+    roles: Mutex<HashSet<String>>, // roles being replayed
+    drained: Notify,               // a role took a record read ahead
+}
+
+impl Player {
+    // Joins the replay of the capture file, starting it if nobody replays it yet
+    fn join(path: &Path, fast: bool, role: &str) -> Result<Arc<Self>, io::Error> {
+        let players = PLAYERS.get_or_init(Default::default);
+        let mut players = players.lock().expect("replay players");
+        let key = (path.to_path_buf(), fast);
+        let player = match players.get(&key) {
+            Some(player) => player.clone(),
+            None => {
+                let file = File::from_std(std::fs::File::open(path)?);
+                let player = Arc::new(Self {
+                    key: key.clone(),
+                    reader: tokio::sync::Mutex::new(Reader {
+                        lines: BufReader::new(file).lines(),
+                        origin: None,
+                        queues: HashMap::new(),
+                    }),
+                    roles: Mutex::new(HashSet::new()),
+                    drained: Notify::new(),
+                });
+                players.insert(key, player.clone());
+                player
+            }
+        };
+        player
+            .roles
+            .lock()
+            .expect("replay roles")
+            .insert(role.to_string());
+        Ok(player)
+    }
+
+    // The last role leaving ends the replay, so that the file is read again from
+    // its start when reopened
+    fn leave(self: &Arc<Self>, role: &str) {
+        let players = PLAYERS.get_or_init(Default::default);
+        let mut players = players.lock().expect("replay players");
+        let mut roles = self.roles.lock().expect("replay roles");
+        roles.remove(role);
+        if roles.is_empty() && players.get(&self.key).is_some_and(|p| Arc::ptr_eq(p, self)) {
+            players.remove(&self.key);
+        }
+        drop(roles);
+        self.drained.notify_waiters();
+    }
+
+    // Another role being replayed fell behind, reading further would only pile up its records
+    fn is_lagging(&self, reader: &Reader, role: &str) -> bool {
+        let roles = self.roles.lock().expect("replay roles");
+        reader
+            .queues
+            .iter()
+            .any(|(r, queue)| r != role && roles.contains(r) && queue.len() >= MAX_LAG)
+    }
+
+    // Cancel safe: records of other roles are queued as soon as read
+    async fn next(&self, role: &str) -> Result<(Line, Instant), io::Error> {
+        loop {
+            let drained = self.drained.notified();
+            let mut reader = self.reader.lock().await;
+            if let Some(line) = reader.queues.get_mut(role).and_then(|q| q.pop_front()) {
+                self.drained.notify_waiters();
+                let due = pace(reader.origin.expect("replay origin"), line.0);
+                return Ok((line, due));
+            }
+            if self.is_lagging(&reader, role) {
+                drop(reader);
+                drained.await;
+                continue;
+            }
+            let (tstamp, record) = reader.next_record().await?;
+            let origin = *reader.origin.get_or_insert((tstamp, Instant::now()));
+            if record.role == role {
+                return Ok(((tstamp, record.line), pace(origin, tstamp)));
+            }
+            // Records of a role nobody replays are only kept while it may still join
+            let joined = self
+                .roles
+                .lock()
+                .expect("replay roles")
+                .contains(&record.role);
+            let queue = reader.queues.entry(record.role).or_default();
+            if joined || queue.len() < MAX_LAG {
+                queue.push_back((tstamp, record.line));
+            }
+        }
+    }
+}
+
+// When a record is due, keeping the original pace from the first record of the file
+fn pace((t0, i0): (Timestamp, Instant), tstamp: Timestamp) -> Instant {
+    i0 + (tstamp - t0).to_std().unwrap_or_default()
+}
+
+pub struct Transport {
+    player: Arc<Player>,
+    role: String,                     // only records of this role are replayed
+    fast: bool,                       // as fast as possible instead of the original pace
+    pending: Option<(Line, Instant)>, // record waiting for its replay time
+}
+
+impl Transport {
+    pub async fn new(path: &Path, role: &str, fast: bool) -> Result<Self, io::Error> {
+        let player = Player::join(path, fast, role)?;
+        Ok(Self {
+            player,
+            role: role.to_string(),
+            fast,
+            pending: None,
+        })
+    }
+
+    // Cancel safe: a record waiting for its replay time is kept for the next call
+    pub async fn reading(&mut self) -> Result<RawSample, io::Error> {
+        if self.pending.is_none() {
+            self.pending = Some(self.player.next(&self.role).await?);
+        }
+        let (_, due) = self.pending.as_ref().expect("pending record");
+        if !self.fast {
+            sleep_until(*due).await;
+        }
+        let ((tstamp, line), _) = self.pending.take().expect("pending record");
+        Ok(RawSample(tstamp, line))
+    }
+}
+
+impl Drop for Transport {
+    fn drop(&mut self) {
+        self.player.leave(&self.role);
+    }
+}
@@ -3,20 +3,51 @@
 use super::RawSample;
 use bytes::BytesMut;
 use chrono::prelude::*;
+use futures::sink::SinkExt;
 use futures::stream::StreamExt;
+use regex::Regex;
 use std::io;
 use std::io::{Error, ErrorKind};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{sleep_until, timeout_at, Duration, Instant};
 use tokio_serial::SerialPortBuilderExt;
-use tokio_serial::SerialStream;
+use tokio_serial::{SerialPortType, SerialStream};
 use tokio_util::codec::{Decoder, Encoder, Framed};
+use tracing::{debug, info, warn};
 
 #[cfg(unix)]
-const DEFAULT_TTY: &str = "/dev/ttyUSB0";
+pub const DEFAULT_TTY: &str = "/dev/ttyUSB0";
 
 #[cfg(windows)]
-const DEFAULT_TTY: &str = "COM1";
+pub const DEFAULT_TTY: &str = "COM1";
 
-struct LineCodec;
+pub const DEFAULT_BAUD: u32 = 9600;
+
+// Device name requesting to probe all serial ports for photometer output
+pub const AUTO_DEVICE: &str = "auto";
+
+const PROBE_SECS: u64 = 5; // listening time on each serial port while probing
+const COMMAND_SECS: u64 = 3; // waiting time for the response to a command
+const COMMANDS_CAPACITY: usize = 4; // commands queued while another one is in progress
+
+// Serial device where a photometer is attached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Port {
+    pub device: String,
+    pub baud: u32,
+}
+
+impl Default for Port {
+    fn default() -> Self {
+        Self {
+            device: DEFAULT_TTY.to_string(),
+            baud: DEFAULT_BAUD,
+        }
+    }
+}
+
+pub struct LineCodec;
 
 impl Decoder for LineCodec {
     type Item = String;
@@ -24,52 +55,236 @@ impl Decoder for LineCodec {
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         let newline = src.as_ref().iter().position(|b| *b == b'\n');
+        // Garbled bytes are kept as replacement characters, so that the payload
+        // decoder rejects the line as malformed instead of the port being reopened
         if let Some(n) = newline {
             let line = src.split_to(n + 1);
-            return match std::str::from_utf8(line.as_ref()) {
-                Ok(s) => Ok(Some(s.to_string())),
-                Err(_) => Err(Error::new(ErrorKind::Other, "Invalid String")),
-            };
+            return Ok(Some(String::from_utf8_lossy(line.as_ref()).into_owned()));
         }
         Ok(None)
     }
 }
 
-// This is not needed for the time being */
+// Commands are sent as newline terminated lines
 impl Encoder<String> for LineCodec {
     type Error = io::Error;
-    fn encode(&mut self, _item: String, _dst: &mut BytesMut) -> Result<(), Self::Error> {
+    fn encode(&mut self, item: String, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        dst.extend_from_slice(item.as_bytes());
+        if !item.ends_with('\n') {
+            dst.extend_from_slice(b"\n");
+        }
         Ok(())
     }
 }
 
-type SerialReader = Framed<SerialStream, LineCodec>;
+// A command to the photometer and the pattern its response line must match
+struct Request {
+    command: String,
+    response: Regex,
+    reply: oneshot::Sender<Result<String, io::Error>>,
+}
 
-pub struct Transport {
-    reader: SerialReader,
+// Sends commands to a photometer while its readings keep streaming
+// through the transport. Handles are cheap to clone.
+#[derive(Clone)]
+pub struct Commander {
+    channel: mpsc::Sender<Request>,
+}
+
+impl Commander {
+    // Returns the first line matching the response pattern
+    pub async fn command(&self, command: &str, response: &Regex) -> Result<String, io::Error> {
+        let (tx, rx) = oneshot::channel();
+        let request = Request {
+            command: command.to_string(),
+            response: response.clone(),
+            reply: tx,
+        };
+        let closed = || Error::new(ErrorKind::BrokenPipe, "Serial transport closed");
+        self.channel.send(request).await.map_err(|_| closed())?;
+        rx.await.map_err(|_| closed())?
+    }
+}
+
+// Generic over the stream so that it can be driven without a serial port
+pub struct Transport<S = SerialStream> {
+    reader: Framed<S, LineCodec>,
+    commander: Commander,
+    requests: mpsc::Receiver<Request>,
+    pending: Option<(Request, Instant)>, // command awaiting its response, and until when
 }
 
 impl Transport {
-    pub async fn new(baud: u32) -> Result<Self, io::Error> {
-        let mut port = tokio_serial::new(DEFAULT_TTY, baud)
-            .open_native_async()
-            .unwrap();
+    pub async fn new(port: &Port) -> Result<Self, io::Error> {
+        let mut port = tokio_serial::new(&port.device, port.baud).open_native_async()?;
         #[cfg(unix)]
-        port.set_exclusive(false)
-            .expect("Unable to set serial port exclusive to false");
-        Ok(Self {
-            reader: LineCodec.framed(port),
-        })
+        port.set_exclusive(false)?;
+        Ok(Self::from_stream(port))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Transport<S> {
+    pub fn from_stream(stream: S) -> Self {
+        let (tx, rx) = mpsc::channel(COMMANDS_CAPACITY);
+        Self {
+            reader: LineCodec.framed(stream),
+            commander: Commander { channel: tx },
+            requests: rx,
+            pending: None,
+        }
+    }
+
+    // Commands are only carried out while the transport is being read.
+    // Handles stop working when the transport is dropped, i.e. on reconnection.
+    pub fn commander(&self) -> Commander {
+        self.commander.clone()
+    }
+
+    // Sends a command and waits for its response, discarding the readings meanwhile.
+    // Meant for talking to the photometer before its readings are of any use.
+    pub async fn command(&mut self, command: &str, response: &Regex) -> Result<String, io::Error> {
+        let commander = self.commander();
+        let reply = commander.command(command, response);
+        tokio::pin!(reply);
+        loop {
+            tokio::select! {
+                result = &mut reply => return result,
+                reading = self.reading() => {
+                    let RawSample(_, line) = reading?;
+                    debug!("Discarding {:?} while waiting for a response", line);
+                }
+            }
+        }
+    }
+
+    // Lines answering the pending command are not readings
+    fn answers(&mut self, line: &str) -> bool {
+        match self.pending {
+            Some((ref request, _)) if request.response.is_match(line) => {
+                if let Some((request, _)) = self.pending.take() {
+                    debug!("Response to {:?} is {:?}", request.command, line);
+                    let _ = request.reply.send(Ok(line.to_string()));
+                }
+                true
+            }
+            _ => false,
+        }
     }
 
     pub async fn reading(&mut self) -> Result<RawSample, io::Error> {
-        if let Some(line_result) = self.reader.next().await {
-            let tstamp = Utc::now();
-            let line = line_result.expect("Failed to read line");
-            let line = line.trim();
-            Ok(RawSample(tstamp, String::from(line)))
-        } else {
-            Err(Error::new(ErrorKind::Other, "No line_result"))
+        loop {
+            let deadline = self.pending.as_ref().map(|(_, deadline)| *deadline);
+            tokio::select! {
+                line_result = self.reader.next() => {
+                    let Some(line_result) = line_result else {
+                        return Err(Error::new(ErrorKind::BrokenPipe, "Serial port closed"));
+                    };
+                    let tstamp = Utc::now();
+                    let line = line_result?;
+                    let line = line.trim();
+                    if !self.answers(line) {
+                        return Ok(RawSample(tstamp, String::from(line)));
+                    }
+                }
+                Some(request) = self.requests.recv(), if self.pending.is_none() => {
+                    debug!("Sending command {:?}", request.command);
+                    match self.reader.send(request.command.clone()).await {
+                        Ok(_) => {
+                            let deadline = Instant::now() + Duration::from_secs(COMMAND_SECS);
+                            self.pending = Some((request, deadline));
+                        }
+                        Err(e) => {
+                            let _ = request.reply.send(Err(e));
+                        }
+                    }
+                }
+                _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                    if let Some((request, _)) = self.pending.take() {
+                        let _ = request.reply.send(Err(Error::new(
+                            ErrorKind::TimedOut,
+                            format!("No response to command {:?}", request.command),
+                        )));
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Listens to a serial port for a while until a line is accepted.
+// Returns the reason why the port was rejected otherwise.
+async fn probe<F>(port: &Port, accept: &F) -> Result<String, String>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut transport = Transport::new(port)
+        .await
+        .map_err(|e| format!("cannot be opened ({e})"))?;
+    let deadline = Instant::now() + Duration::from_secs(PROBE_SECS);
+    let mut nlines = 0;
+    loop {
+        match timeout_at(deadline, transport.reader.next()).await {
+            Err(_) if nlines == 0 => return Err(format!("no output in {PROBE_SECS} s")),
+            Err(_) => return Err(format!("{nlines} lines read, none from a photometer")),
+            Ok(None) => return Err("closed while reading".to_string()),
+            Ok(Some(Err(e))) => return Err(format!("read error ({e})")),
+            Ok(Some(Ok(line))) => {
+                let line = line.trim();
+                if accept(line) {
+                    return Ok(line.to_string());
+                }
+                nlines += 1;
+            }
+        }
+    }
+}
+
+// Probes all available serial ports at the same time, but the one already taken
+// by the other photometer, and picks the first one, USB ports first, that emits
+// an accepted line
+pub async fn detect<F>(baud: u32, taken: Option<&str>, accept: F) -> Result<Port, io::Error>
+where
+    F: Fn(&str) -> bool,
+{
+    let mut available = tokio_serial::available_ports()?;
+    available.retain(|p| Some(p.port_name.as_str()) != taken);
+    available.sort_by_key(|p| {
+        (
+            !matches!(p.port_type, SerialPortType::UsbPort(_)),
+            p.port_name.clone(),
+        )
+    });
+    let ports: Vec<Port> = available
+        .into_iter()
+        .map(|p| Port {
+            device: p.port_name,
+            baud,
+        })
+        .collect();
+    info!(
+        "Probing {} serial ports at {} bauds for photometer output",
+        ports.len(),
+        baud
+    );
+    let results = futures::future::join_all(ports.iter().map(|p| probe(p, &accept))).await;
+    let mut chosen = None;
+    for (port, result) in ports.into_iter().zip(results) {
+        match result {
+            Ok(line) if chosen.is_none() => {
+                info!("Chosen serial port {}: read {:?}", port.device, line);
+                chosen = Some(port);
+            }
+            Ok(_) => warn!(
+                "Serial port {} rejected: another port was chosen first",
+                port.device
+            ),
+            Err(reason) => info!("Serial port {} rejected: {}", port.device, reason),
         }
     }
+    chosen.ok_or_else(|| {
+        Error::new(
+            ErrorKind::NotFound,
+            "No serial port with photometer output found",
+        )
+    })
 }
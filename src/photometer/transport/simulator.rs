@@ -0,0 +1,312 @@
+// Synthetic photometer generating TESS-W JSON datagrams or Cristogg serial lines
+
+use super::RawSample;
+use crate::Timestamp;
+use anyhow::{anyhow, bail};
+use chrono::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::Notify;
+use tokio::time::{sleep_until, Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    Cristogg,
+}
+
+// Simulation parameters, given as <format>[,<key>=<value>...] in the endpoint
+#[derive(Debug, Clone, PartialEq)]
+pub struct Params {
+    pub format: Format,
+    pub name: String,
+    pub freq: f32,   // initial frequency, Hz
+    pub noise: f32,  // gaussian noise standard deviation, Hz
+    pub drift: f32,  // frequency drift, Hz per minute
+    pub gap: f32,    // probability of losing readings (JSON sequence number gaps)
+    pub dup: f32,    // probability of repeating the previous reading
+    pub period: f32, // reporting period, seconds
+    pub zp: f32,
+    pub seed: u64,
+    pub fast: bool, // no waiting, timestamps advance by the reporting period
+}
+
+impl Params {
+    fn new(format: Format) -> Self {
+        Self {
+            format,
+            name: "stars0".to_string(),
+            freq: 5.0,
+            noise: 0.01,
+            drift: 0.0,
+            gap: 0.0,
+            dup: 0.0,
+            period: match format {
+                Format::Json => 1.0,
+                Format::Cristogg => 2.0,
+            },
+            zp: 20.5,
+            seed: 1,
+            fast: false,
+        }
+    }
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> anyhow::Result<T> {
+    value
+        .parse::<T>()
+        .map_err(|_| anyhow!("Invalid value {} for simulation parameter {}", value, key))
+}
+
+impl FromStr for Params {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut items = s.split(',');
+        let mut params = match items.next() {
+            Some("json") => Params::new(Format::Json),
+            Some("cristogg") => Params::new(Format::Cristogg),
+            _ => bail!("Simulation format must be either json or cristogg: {}", s),
+        };
+        for item in items {
+            let Some((key, value)) = item.split_once('=') else {
+                bail!("Simulation parameter must be <key>=<value>: {}", item);
+            };
+            match key {
+                "name" => params.name = value.to_string(),
+                "freq" => params.freq = parse_value(key, value)?,
+                "noise" => params.noise = parse_value(key, value)?,
+                "drift" => params.drift = parse_value(key, value)?,
+                "gap" => params.gap = parse_value(key, value)?,
+                "dup" => params.dup = parse_value(key, value)?,
+                "period" => params.period = parse_value(key, value)?,
+                "zp" => params.zp = parse_value(key, value)?,
+                "seed" => params.seed = parse_value(key, value)?,
+                "fast" => params.fast = parse_value(key, value)?,
+                _ => bail!("Unknown simulation parameter {}", key),
+            }
+        }
+        if !(params.period.is_finite() && params.period > 0.0) {
+            bail!("Simulation period must be positive");
+        }
+        // Readings are lost or repeated until a random draw says otherwise
+        for (key, value) in [("gap", params.gap), ("dup", params.dup)] {
+            if !(0.0..1.0).contains(&value) {
+                bail!(
+                    "Simulation parameter {} must be within [0, 1): {}",
+                    key,
+                    value
+                );
+            }
+        }
+        Ok(params)
+    }
+}
+
+impl fmt::Display for Params {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let format = match self.format {
+            Format::Json => "json",
+            Format::Cristogg => "cristogg",
+        };
+        write!(
+            f,
+            "{},name={},freq={},noise={},drift={},gap={},dup={},period={},zp={},seed={},fast={}",
+            format,
+            self.name,
+            self.freq,
+            self.noise,
+            self.drift,
+            self.gap,
+            self.dup,
+            self.period,
+            self.zp,
+            self.seed,
+            self.fast
+        )
+    }
+}
+
+// xorshift64* generator, good enough for noise and reproducible from a seed
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    // Uniform in [0, 1)
+    fn uniform(&mut self) -> f32 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545F4914F6CDD1D) >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // Standard normal, Box-Muller transform
+    fn gaussian(&mut self) -> f32 {
+        let u1 = self.uniform().max(f32::MIN_POSITIVE);
+        let u2 = self.uniform();
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f32::consts::PI * u2).cos()
+    }
+}
+
+// Simulated time shared by the fast simulators, so that they all run at the same speed:
+// a simulator only emits its next reading when no other one is due earlier
+struct Clock {
+    start: Timestamp,
+    due: Mutex<HashMap<u64, f32>>, // next reading of each fast simulator, seconds since start
+    tick: Notify,
+    ids: AtomicU64,
+}
+
+static CLOCK: OnceLock<Clock> = OnceLock::new();
+
+impl Clock {
+    fn get() -> &'static Self {
+        CLOCK.get_or_init(|| Self {
+            start: Utc::now(),
+            due: Mutex::new(HashMap::new()),
+            tick: Notify::new(),
+            ids: AtomicU64::new(0),
+        })
+    }
+
+    fn join(&self) -> u64 {
+        let id = self.ids.fetch_add(1, Ordering::Relaxed);
+        self.due.lock().expect("simulation clock").insert(id, 0.0);
+        id
+    }
+
+    fn leave(&self, id: u64) {
+        self.due.lock().expect("simulation clock").remove(&id);
+        self.tick.notify_waiters();
+    }
+
+    fn is_next(&self, id: u64) -> bool {
+        let due = self.due.lock().expect("simulation clock");
+        let mine = due.get(&id).copied().unwrap_or_default();
+        due.values().all(|other| mine <= *other)
+    }
+
+    // Cancel safe: nothing changes until the simulator's turn comes
+    async fn wait_turn(&self, id: u64) {
+        loop {
+            let tick = self.tick.notified();
+            if self.is_next(id) {
+                return;
+            }
+            tick.await;
+        }
+    }
+
+    fn advance(&self, id: u64, elapsed: f32) {
+        self.due
+            .lock()
+            .expect("simulation clock")
+            .insert(id, elapsed);
+        self.tick.notify_waiters();
+    }
+}
+
+pub struct Transport {
+    params: Params,
+    rng: Rng,
+    seq: u32,                 // JSON udp counter
+    elapsed: f32,             // seconds since the simulation started
+    clock: Option<u64>,       // turn in the shared clock, fast simulators only
+    previous: Option<String>, // last line emitted, to be repeated
+    next_at: Instant,         // when the next line is due, kept across cancellations
+}
+
+impl Transport {
+    pub fn new(params: &Params) -> Self {
+        Self {
+            rng: Rng::new(params.seed),
+            params: params.clone(),
+            seq: 0,
+            elapsed: 0.0,
+            clock: params.fast.then(|| Clock::get().join()),
+            previous: None,
+            next_at: Instant::now() + Duration::from_secs_f32(params.period),
+        }
+    }
+
+    fn json_line(&self, freq: f32, tamb: f32, tsky: f32) -> String {
+        let mag = self.params.zp - 2.5 * freq.log10();
+        format!(
+            "{{\"udp\":{},\"rev\":2,\"name\":\"{}\",\"freq\":{:.3},\"mag\":{:.2},\"tamb\":{:.2},\"tsky\":{:.2},\"wdBm\":-60,\"ain\":500,\"ZP\":{:.2}}}",
+            self.seq, self.params.name, freq, mag, tamb, tsky, self.params.zp
+        )
+    }
+
+    // Millihertz resolution below 100 Hz, as the reference photometer does
+    fn cristogg_line(&self, freq: f32, tamb: f32, tsky: f32) -> String {
+        let freq = if freq < 100.0 {
+            format!("<fm {:05}>", (freq * 1000.0).round() as u32)
+        } else {
+            format!("<fH {:05}>", freq.round() as u32)
+        };
+        format!(
+            "{}<tA {:+05}><tO {:+05}><mZ {:+05}>",
+            freq,
+            (tamb * 100.0).round() as i32,
+            (tsky * 100.0).round() as i32,
+            (self.params.zp * 100.0).round() as i32
+        )
+    }
+
+    fn next_line(&mut self) -> String {
+        if let Some(ref line) = self.previous {
+            if self.rng.uniform() < self.params.dup {
+                return line.clone();
+            }
+        }
+        self.seq += 1;
+        while self.rng.uniform() < self.params.gap {
+            self.seq += 1; // a lost reading
+        }
+        let freq = self.params.freq
+            + self.params.drift * self.elapsed / 60.0
+            + self.params.noise * self.rng.gaussian();
+        let freq = freq.max(0.001);
+        let tamb = 20.0 + 0.1 * self.rng.gaussian();
+        let tsky = tamb - 5.0 + 0.1 * self.rng.gaussian();
+        let line = match self.params.format {
+            Format::Json => self.json_line(freq, tamb, tsky),
+            Format::Cristogg => self.cristogg_line(freq, tamb, tsky),
+        };
+        self.previous = Some(line.clone());
+        line
+    }
+
+    pub async fn reading(&mut self) -> Result<RawSample, io::Error> {
+        let tstamp = match self.clock {
+            Some(id) => {
+                let clock = Clock::get();
+                clock.wait_turn(id).await;
+                clock.advance(id, self.elapsed + self.params.period);
+                clock.start + chrono::Duration::milliseconds((self.elapsed * 1000.0) as i64)
+            }
+            None => {
+                sleep_until(self.next_at).await;
+                self.next_at += Duration::from_secs_f32(self.params.period);
+                Utc::now()
+            }
+        };
+        self.elapsed += self.params.period;
+        Ok(RawSample(tstamp, self.next_line()))
+    }
+}
+
+impl Drop for Transport {
+    fn drop(&mut self) {
+        if let Some(id) = self.clock {
+            Clock::get().leave(id);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+// TCP Stuff, i.e. a serial line exposed through a serial-to-Ethernet bridge
+
+use super::serial::LineCodec;
+use super::RawSample;
+use chrono::prelude::*;
+use futures::stream::StreamExt;
+use std::io;
+use std::io::{Error, ErrorKind};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+use tokio_util::codec::{Decoder, Framed};
+
+type TcpReader = Framed<TcpStream, LineCodec>;
+
+const CONNECT_SECS: u64 = 5; // unreachable bridges would otherwise block for minutes
+
+pub struct Transport {
+    reader: TcpReader,
+}
+
+impl Transport {
+    pub async fn new(host: &str, port: u16) -> Result<Self, io::Error> {
+        let stream = timeout(
+            Duration::from_secs(CONNECT_SECS),
+            TcpStream::connect((host, port)),
+        )
+        .await
+        .map_err(|_| Error::new(ErrorKind::TimedOut, "TCP connection timed out"))??;
+        Ok(Self {
+            reader: LineCodec.framed(stream),
+        })
+    }
+
+    pub async fn reading(&mut self) -> Result<RawSample, io::Error> {
+        match self.reader.next().await {
+            Some(line_result) => {
+                let tstamp = Utc::now();
+                let line = line_result?;
+                Ok(RawSample(tstamp, String::from(line.trim())))
+            }
+            None => Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                "TCP connection closed",
+            )),
+        }
+    }
+}
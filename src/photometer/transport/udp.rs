@@ -1,37 +1,128 @@
 use super::RawSample;
 use bytes::BytesMut;
 use chrono::prelude::*;
+use regex::Regex;
+use std::collections::HashSet;
+use std::fmt;
 use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::OnceLock;
 use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
 
-const BUF_SIZE: usize = 256;
-const ANY_ADDR: &str = "0.0.0.0";
+const BUF_SIZE: usize = 1500; // Ethernet MTU, the longest datagram a photometer sends
+
+// Selects which photometer to listen to when several share the same UDP port.
+// An empty filter accepts datagrams from anybody.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter {
+    pub from: Option<IpAddr>,
+    pub name: Option<String>,
+    pub shared: bool, // several test photometers are expected, not part of the endpoint spec
+}
+
+impl Filter {
+    pub fn is_empty(&self) -> bool {
+        self.from.is_none() && self.name.is_none()
+    }
+
+    pub fn accepts(&self, src: &SocketAddr, line: &str) -> bool {
+        if let Some(from) = self.from {
+            if src.ip() != from {
+                return false;
+            }
+        }
+        match self.name {
+            Some(ref name) => photometer_name(line).as_deref() == Some(name.as_str()),
+            None => true,
+        }
+    }
+}
+
+// Rendered as the ,<key>=<value> suffix of the UDP endpoint
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(from) = self.from {
+            write!(f, ",from={}", from)?;
+        }
+        if let Some(ref name) = self.name {
+            write!(f, ",name={}", name)?;
+        }
+        Ok(())
+    }
+}
+
+const NAME: &str = r#""name"\s*:\s*"([^"]*)""#;
+
+// The photometer name carried by a JSON datagram, without decoding the whole payload.
+// Truncated or corrupted datagrams are still told apart by the name they carry, if any.
+pub fn photometer_name(line: &str) -> Option<String> {
+    match serde_json::from_str::<serde_json::Value>(line) {
+        Ok(value) => value.get("name")?.as_str().map(String::from),
+        Err(_) => {
+            static RE: OnceLock<Regex> = OnceLock::new();
+            let re = RE.get_or_init(|| Regex::new(NAME).unwrap());
+            re.captures(line).map(|c| c[1].to_string())
+        }
+    }
+}
 
 pub struct Transport {
     socket: UdpSocket,
     buffer: BytesMut,
+    filter: Filter,
+    senders: HashSet<IpAddr>, // accepted so far
 }
 
 impl Transport {
-    pub async fn new(port: u16) -> Result<Self, io::Error> {
-        let mut endpoint = String::from(ANY_ADDR);
-        endpoint.push(':');
-        endpoint.push_str(&port.to_string());
+    pub async fn new(host: &str, port: u16, filter: &Filter) -> Result<Self, io::Error> {
         Ok(Self {
-            socket: UdpSocket::bind(endpoint).await?,
+            socket: UdpSocket::bind((host, port)).await?,
             buffer: BytesMut::with_capacity(BUF_SIZE),
+            filter: filter.clone(),
+            senders: HashSet::new(),
         })
     }
 
-    pub async fn reading(&mut self) -> Result<RawSample, io::Error> {
-        let (len, _src) = self.socket.recv_buf_from(&mut self.buffer).await?;
+    // Next datagram from any sender, together with its source address
+    async fn receive(&mut self) -> Result<(SocketAddr, RawSample), io::Error> {
+        let (len, src) = self.socket.recv_buf_from(&mut self.buffer).await?;
         let tstamp = Utc::now();
-        let s = String::from(
-            std::str::from_utf8(&self.buffer[..len])
-                .expect("invalid UTF-8")
-                .trim(),
-        );
+        let s = String::from_utf8_lossy(&self.buffer[..len])
+            .trim()
+            .to_string();
         self.buffer.clear();
-        Ok(RawSample(tstamp, s))
+        Ok((src, RawSample(tstamp, s)))
+    }
+
+    // Next datagram accepted by the filter, together with its source address.
+    // Other senders are discarded.
+    pub async fn reading_from(&mut self) -> Result<(SocketAddr, RawSample), io::Error> {
+        loop {
+            let (src, raw_sample) = self.receive().await?;
+            if !self.filter.accepts(&src, &raw_sample.1) {
+                debug!("Discarding UDP datagram from {}", src);
+                continue;
+            }
+            let ip = src.ip();
+            if self.senders.insert(ip) {
+                if self.senders.len() > 1 && self.filter.is_empty() && !self.filter.shared {
+                    warn!(
+                        "UDP datagrams from {} as well, select one photometer with from= or name=",
+                        ip
+                    );
+                } else {
+                    info!("Receiving UDP datagrams from {}", src);
+                }
+            }
+            return Ok((src, raw_sample));
+        }
+    }
+
+    // Photometers sharing the port are told apart downstream by the name in their
+    // JSON readings, which every one of them carries, not by their source address
+    pub async fn reading(&mut self) -> Result<RawSample, io::Error> {
+        let (_, raw_sample) = self.reading_from().await?;
+        Ok(raw_sample)
     }
 }
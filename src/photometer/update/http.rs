@@ -6,6 +6,16 @@ const URL_SET_ZP_V1: &str = "http://192.168.4.1/SetZP";
 const URL_SET_ZP_V2: &str = "http://192.168.4.1/setconst";
 const URL_GET_ZP: &str = "http://192.168.4.1/config";
 const ZP: &str = r"(ZP|CI.*): (\d{1,2}\.\d{1,2})";
+const ZP_TOLERANCE: f32 = 0.005; // half the resolution of the zero points written
+
+// Zero points are written and read back with two decimals
+fn round_zp(zp: f32) -> f32 {
+    (zp * 100.0).round() / 100.0
+}
+
+fn same_zp(read_zp: f32, written_zp: f32) -> bool {
+    (read_zp - written_zp).abs() < ZP_TOLERANCE
+}
 
 pub struct Updater {
     re: Regex,
@@ -25,6 +35,7 @@ impl Updater {
     }
 
     pub async fn update_zp(&self, zp: f32) -> Result<()> {
+        let zp = round_zp(zp);
         let param1 = vec![("nZP1", format!("{zp:.02}"))];
         let param2 = vec![("cons", format!("{zp:.02}"))];
         let client = reqwest::Client::builder()
@@ -49,7 +60,7 @@ impl Updater {
             bail!("Parsing TESS-W HTML page");
         };
         ensure!(
-            read_zp == written_zp,
+            same_zp(read_zp, written_zp),
             "Read ZP ({:.02}) doesn't match written ZP ({:.02})",
             read_zp,
             written_zp
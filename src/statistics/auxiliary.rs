@@ -45,18 +45,19 @@ pub fn magntude(freq: f32, freq_offset: f32, zp: f32) -> f32 {
     zp - 2.5 * (freq - freq_offset).log10()
 }
 
-pub fn mode_or_median(v: &[f32], precision: u32, label: &str) -> f32 {
+// Also returns the method used, as stored in the database
+pub fn mode_or_median(v: &[f32], precision: u32, label: &str) -> (f32, &'static str) {
     let v1: Vec<i32> = v
         .iter()
         .map(|x| (*x * (10u32.pow(precision)) as f32).round() as i32)
         .collect();
     if let Some(mode) = mode(&v1) {
-        mode as f32 / (10u32.pow(precision) as f32)
+        (mode as f32 / (10u32.pow(precision) as f32), "mode")
     } else {
         warn!(
             "Mode for {} does not exists, calculating median instead",
             label
         );
-        statistical::median(v)
+        (statistical::median(v), "median")
     }
 }
@@ -1,177 +1,729 @@
 use super::{
-    CalibrationInfo, Info, Payload, Pool, Sample, SamplesBuffer, TimeWindow, Timestamp, LABEL, REF,
-    TEST,
+    CalibrationInfo, Info, Payload, Pool, RoundOptions, Sample, SamplesBuffer, SessionOptions,
+    TimeWindow, Timestamp, LABEL, REF, ROLE, TEST,
 };
+use crate::photometer::payload::{DecodeStats, Sequence};
+use crate::photometer::profile::{Format, Profile};
+use crate::photometer::{Event, Status};
+use crate::Role;
 
+use crate::database::models::{self, Round, Summary};
 use crate::statistics::auxiliary;
-use crate::statistics::dao;
-use anyhow::Result;
+use crate::statistics::dao::{self, channel_key, format_tstamp};
+use anyhow::{bail, Result};
 use chrono::SecondsFormat;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 use tokio::sync::mpsc::Receiver;
-use tokio::time::{Duration, Instant};
-use tracing::info;
+use tracing::{info, warn};
+
+const CENTRAL: &str = "median"; // central tendency estimator used in every round
+
+// Final zero point of a test photometer, by name and channel
+pub type ZeroPoint = (String, Option<u8>, f32);
+
+// A photometer taking part in the calibration, with its results round after round
+struct Track {
+    buffer: SamplesBuffer,
+    channel: Option<u8>,      // of a multi-channel photometer
+    format: Option<Format>,   // of the payloads, as detected by the reading task
+    decoded: DecodeStats,     // lines received up to the previous round
+    freqs: Vec<f32>,          // median frequency for each round
+    stdevs: Vec<f32>,         // standard deviation for each round
+    mags: Vec<f32>,           // magnitude for each round
+    zps: Vec<f32>,            // zero point for each round, test photometers only
+    tstamps: Vec<TimeWindow>, // timestamps limits for each round
+    durs: Vec<f32>,           // duration of each round
+    last: Option<Timestamp>,  // of the last sample received
+    silent: bool,             // no samples for too long, while others keep coming
+}
+
+impl Track {
+    fn new(buffer: SamplesBuffer, channel: Option<u8>, nrounds: usize) -> Self {
+        Self {
+            buffer,
+            channel,
+            format: None,
+            decoded: DecodeStats::default(),
+            freqs: Vec::with_capacity(nrounds),
+            stdevs: Vec::with_capacity(nrounds),
+            mags: Vec::with_capacity(nrounds),
+            zps: Vec::with_capacity(nrounds),
+            tstamps: Vec::with_capacity(nrounds),
+            durs: Vec::with_capacity(nrounds),
+            last: None,
+            silent: false,
+        }
+    }
+
+    fn name(&self) -> &str {
+        &self.buffer.info.name
+    }
+
+    // Photometer name and channel, if any
+    fn label(&self) -> String {
+        match self.channel {
+            Some(channel) => format!("{}/{}", self.name(), channel),
+            None => self.name().to_string(),
+        }
+    }
+
+    fn accumulate(&mut self, freq: f32, stdev: f32, mag: f32, w: TimeWindow, dur: f32) {
+        self.freqs.push(freq);
+        self.stdevs.push(stdev);
+        self.mags.push(mag);
+        self.tstamps.push(w);
+        self.durs.push(dur);
+    }
+}
 
 pub struct Calibration {
     session: Timestamp,
     info: CalibrationInfo,
-    refe: SamplesBuffer,
-    test: SamplesBuffer,
-    ready: bool, // Global redy flag computed from the two samples buffers
+    options: SessionOptions,
+    refe: Track,
+    tests: Vec<Track>,          // test photometers in order of appearance
+    test_info: Option<Info>,    // discovered test photometer, matched by name
+    profile: &'static Profile,  // of the test photometers
+    strangers: HashSet<String>, // unexpected test photometers already warned about
+    ntests: usize,              // number of test photometers calibrated at once
+    window: usize,
+    nrounds: usize,
+    ready: bool, // Global redy flag computed from all the samples buffers
     round: usize,
-    millis: u64, // Number of milliseconds to wait between rounds, usually 5000
+    millis: u64,       // Number of milliseconds to wait between rounds, usually 5000
+    silence: Duration, // without samples before a test photometer is silent
     channel: Receiver<Sample>, // where to receive the sampels form photometer tasks
-    freqs: [Vec<f32>; 2], // median frequency for the current round
-    stdevs: [Vec<f32>; 2], //  Stanbdard deviations for the current round
-    mags: [Vec<f32>; 2], //  magnitude for the current round
-    zps: Vec<f32>, //  zero point for the current round
-    tstamps: [Vec<TimeWindow>; 2], //  timestamps limits for the current round
-    durs: [Vec<f32>; 2], //  Turations for the crrent round
+    events: Receiver<Event>, // where to receive link status changes from photometer tasks
+    paused: [bool; 2], // photometer link is down
+    resume_at: Option<Timestamp>, // samples before the last reconnection are not used
+    ended: Option<String>, // why a reading task is over, once it tells so
+    decoding: [HashMap<String, DecodeStats>; 2], // lines received, by role and photometer name
+}
+
+// Photometer name as the reading tasks key their decoders, empty if the readings carry none
+fn stream_key(payload: &Payload) -> &str {
+    match payload {
+        Payload::Json(json) => &json.name,
+        Payload::Cristogg(_) => "",
+    }
 }
 
 impl Calibration {
     fn new(
-        window: usize,
-        session: Timestamp,
         channel: Receiver<Sample>,
-        nrounds: usize,
-        millis: u64,
+        events: Receiver<Event>,
         ref_info: Info,
         test_info: Info,
         info: CalibrationInfo,
+        rounds: RoundOptions,
+        options: SessionOptions,
     ) -> Self {
+        let RoundOptions {
+            window,
+            nrounds,
+            ntests,
+            millis,
+            profile,
+            silence,
+        } = rounds;
+        let refe = SamplesBuffer::new(window, ref_info, LABEL[REF], info.zp_fict);
+        let mut tests = Vec::with_capacity(ntests);
+        // A single test photometer takes every JSON reading, whatever its name
+        let test_info = if ntests == 1 {
+            let buffer = SamplesBuffer::new(window, test_info, LABEL[TEST], info.zp_fict);
+            tests.push(Track::new(buffer, None, nrounds));
+            None
+        } else {
+            Some(test_info)
+        };
         Self {
-            session,
-            refe: SamplesBuffer::new(window, ref_info, LABEL[REF], info.zp_fict),
-            test: SamplesBuffer::new(window, test_info, LABEL[TEST], info.zp_fict),
+            session: options.session,
+            options,
+            events,
+            paused: [false, false],
+            resume_at: None,
+            ended: None,
+            decoding: [HashMap::new(), HashMap::new()],
+            refe: Track::new(refe, None, nrounds),
+            tests,
+            test_info,
+            profile,
+            strangers: HashSet::new(),
+            ntests,
+            window,
+            nrounds,
             info,
             ready: false,
             round: 1,
-            millis,  // Milliseconds to wait between rounds, usually 5000
+            millis, // Milliseconds to wait between rounds, usually 5000
+            silence,
             channel, // Take ownership of the receiver end of the channel
-            freqs: [
-                Vec::<f32>::with_capacity(nrounds),
-                Vec::<f32>::with_capacity(nrounds),
-            ],
-            stdevs: [
-                Vec::<f32>::with_capacity(nrounds),
-                Vec::<f32>::with_capacity(nrounds),
-            ],
-            mags: [
-                Vec::<f32>::with_capacity(nrounds),
-                Vec::<f32>::with_capacity(nrounds),
-            ],
-            tstamps: [
-                Vec::<TimeWindow>::with_capacity(nrounds),
-                Vec::<TimeWindow>::with_capacity(nrounds),
-            ],
-            durs: [
-                Vec::<f32>::with_capacity(nrounds),
-                Vec::<f32>::with_capacity(nrounds),
-            ],
-            zps: Vec::<f32>::with_capacity(nrounds),
-        }
-    }
-
-    fn accumulate(&mut self, idx: usize, freq: f32, stdev: f32, mag: f32, w: TimeWindow, dur: f32) {
-        self.freqs[idx].push(freq);
-        self.stdevs[idx].push(stdev);
-        self.mags[idx].push(mag);
-        self.tstamps[idx].push(w);
-        self.durs[idx].push(dur);
-    }
-
-    async fn one_round(&mut self, round: usize) {
-        self.round = round;
-        let begin = Instant::now();
-        while let Some(message) = self.channel.recv().await {
-            match message {
-                (tstamp, Payload::Json(reading)) => {
-                    self.test
-                        .possibly_enqueue(tstamp, Payload::Json(reading), self.ready);
-                    self.ready = self.refe.ready && self.test.ready;
+        }
+    }
+
+    // Test photometers are told apart by the name and channel in their JSON readings.
+    // They join the calibration as they appear, up to the expected number.
+    fn test_index(&mut self, payload: &Payload) -> Option<usize> {
+        if self.ntests == 1 {
+            return Some(0);
+        }
+        let Payload::Json(payload) = payload else {
+            if self.strangers.insert(String::new()) {
+                warn!(
+                    "{} readings without a photometer name are ignored, as there are {} of them",
+                    LABEL[TEST], self.ntests
+                );
+            }
+            return None;
+        };
+        if let Some(idx) = self
+            .tests
+            .iter()
+            .position(|t| t.name() == payload.name && t.channel == payload.channel)
+        {
+            return Some(idx);
+        }
+        if self.tests.len() == self.ntests {
+            if self.strangers.insert(payload.name.clone()) {
+                warn!(
+                    "{} photometer {} not expected, its readings are ignored",
+                    LABEL[TEST], payload.name
+                );
+            }
+            return None;
+        }
+        let info = match self.test_info {
+            Some(ref info) if info.name == payload.name => info.clone(),
+            _ => Info::from_reading(self.profile, payload),
+        };
+        let buffer = SamplesBuffer::for_test(self.window, info, payload, self.info.zp_fict);
+        let track = Track::new(buffer, payload.channel, self.nrounds);
+        info!(
+            "{} photometer {} joins the calibration ({} of {})",
+            LABEL[TEST],
+            track.label(),
+            self.tests.len() + 1,
+            self.ntests
+        );
+        self.tests.push(track);
+        Some(self.tests.len() - 1)
+    }
+
+    fn test_names(&self) -> String {
+        match self.tests.len() {
+            0 => "(none yet)".to_string(),
+            _ => self
+                .tests
+                .iter()
+                .map(|t| t.label())
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+
+    fn on_event(&mut self, event: Event) -> Result<()> {
+        let (idx, name) = match event.role {
+            Role::Refe => (REF, self.refe.name().to_string()),
+            Role::Test => (TEST, self.test_names()),
+        };
+        match event.status {
+            Status::Disconnected(reason) => {
+                warn!(
+                    "{} photometer {} disconnected ({}), calibration paused",
+                    LABEL[idx], name, reason
+                );
+                self.paused[idx] = true;
+            }
+            Status::Reconnected => {
+                info!(
+                    "{} photometer {} reconnected, calibration resumes with fresh samples",
+                    LABEL[idx], name
+                );
+                self.paused[idx] = false;
+                self.resume_at = Some(event.tstamp);
+            }
+            Status::Silent(secs) if self.options.pause_on_silence => {
+                warn!(
+                    "{} photometer {} silent for {} s, calibration paused",
+                    LABEL[idx], name, secs
+                );
+                self.paused[idx] = true;
+            }
+            Status::Silent(secs) => {
+                bail!(
+                    "{} photometer {} silent for {} s, calibration aborted",
+                    LABEL[idx],
+                    name,
+                    secs
+                );
+            }
+            Status::Resumed => {
+                info!(
+                    "{} photometer {} readings resumed, calibration resumes with fresh samples",
+                    LABEL[idx], name
+                );
+                self.paused[idx] = false;
+                self.resume_at = Some(event.tstamp);
+            }
+            Status::Rejected(key, stats) => {
+                self.decoding[idx]
+                    .entry(key)
+                    .or_default()
+                    .rejections(&stats);
+            }
+            Status::Sequence(key, sequence) => {
+                if sequence == Sequence::Reboot {
+                    warn!(
+                        "{} photometer {} rebooted, round {} discarded, calibration resumes with fresh samples",
+                        LABEL[idx], name, self.round
+                    );
+                    self.resume_at = Some(event.tstamp);
                 }
-                (tstamp, Payload::Cristogg(reading)) => {
-                    self.refe
-                        .possibly_enqueue(tstamp, Payload::Cristogg(reading), self.ready);
-                    self.ready = self.refe.ready && self.test.ready;
+                self.decoding[idx]
+                    .entry(key)
+                    .or_default()
+                    .sequence(sequence);
+            }
+            Status::Ended(reason) => {
+                let reason = reason.unwrap_or_else(|| "no more readings".to_string());
+                self.ended = Some(format!(
+                    "{} photometer {} readings ended ({}), calibration aborted",
+                    LABEL[idx], name, reason
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // A single photometer per role takes every line of its reading task, whatever the name
+    fn decode_stats(&self, idx: usize, track: &Track) -> DecodeStats {
+        if idx == REF || self.ntests == 1 {
+            self.decoding[idx]
+                .values()
+                .fold(DecodeStats::default(), |mut total, stats| {
+                    total.add(stats);
+                    total
+                })
+        } else {
+            self.decoding[idx]
+                .get(track.name())
+                .cloned()
+                .unwrap_or_default()
+        }
+    }
+
+    // Readings received so far and lost in this round, by photometer
+    fn report_decoding(&mut self) {
+        let mut totals = vec![self.decode_stats(REF, &self.refe)];
+        totals.extend(self.tests.iter().map(|test| self.decode_stats(TEST, test)));
+        let tracks = std::iter::once(&mut self.refe).chain(self.tests.iter_mut());
+        for (track, total) in tracks.zip(totals) {
+            info!(
+                "ROUND {:02}: {:9} Readings {}",
+                self.round,
+                track.label(),
+                total
+            );
+            let round = DecodeStats {
+                accepted: total.accepted - track.decoded.accepted,
+                lost: total.lost - track.decoded.lost,
+                ..Default::default()
+            };
+            info!(
+                "ROUND {:02}: {:9} Lost {:.1}% of the readings ({} of {})",
+                self.round,
+                track.label(),
+                round.loss(),
+                round.lost,
+                round.accepted + round.lost
+            );
+            track.decoded = total;
+        }
+    }
+
+    // Test photometers sharing a stream may go silent one by one, unnoticed by
+    // the watchdog of their reading task as long as the others keep sending
+    fn watch_tests(&mut self, tstamp: Timestamp) -> Result<()> {
+        if self.ntests == 1 {
+            return Ok(());
+        }
+        for test in self.tests.iter_mut() {
+            let Some(last) = test.last else {
+                continue;
+            };
+            let silent_for = (tstamp - last).to_std().unwrap_or_default();
+            if test.silent || silent_for < self.silence {
+                continue;
+            }
+            if !self.options.pause_on_silence {
+                bail!(
+                    "{} photometer {} silent for {} s, calibration aborted",
+                    LABEL[TEST],
+                    test.label(),
+                    silent_for.as_secs()
+                );
+            }
+            warn!(
+                "{} photometer {} silent for {} s, calibration paused",
+                LABEL[TEST],
+                test.label(),
+                silent_for.as_secs()
+            );
+            test.silent = true;
+        }
+        Ok(())
+    }
+
+    // All links are up and the round windows only hold samples taken after the last reconnection
+    fn resumed(&self) -> bool {
+        if self.paused.iter().any(|p| *p) || self.tests.iter().any(|t| t.silent) {
+            return false;
+        }
+        match self.resume_at {
+            None => true,
+            Some(t) => std::iter::once(&self.refe)
+                .chain(self.tests.iter())
+                .all(|track| track.buffer.window_start().is_some_and(|t0| t0 >= t)),
+        }
+    }
+
+    fn all_ready(&self) -> bool {
+        self.refe.buffer.ready
+            && self.tests.len() == self.ntests
+            && self.tests.iter().all(|t| t.buffer.ready)
+    }
+
+    // Every test photometer sent samples during the round, so that none of them
+    // is calibrated with the window it had before falling silent
+    fn all_sent_since(&self, begin: Timestamp) -> bool {
+        self.ntests == 1
+            || self
+                .tests
+                .iter()
+                .all(|t| t.last.is_some_and(|l| l >= begin))
+    }
+
+    // Rounds are timed with the samples timestamps, not the wall clock,
+    // so that replayed readings can be fed as fast as possible
+    async fn one_round(&mut self, round: usize) -> Result<()> {
+        self.round = round;
+        let mut begin: Option<Timestamp> = None;
+        loop {
+            let message = match self.ended {
+                // Samples queued before the readings ended may still complete the round
+                Some(ref reason) => match self.channel.try_recv() {
+                    Ok(message) => Some(message),
+                    Err(_) => bail!("{}", reason),
+                },
+                None => tokio::select! {
+                    message = self.channel.recv() => message,
+                    Some(event) = self.events.recv() => {
+                        self.on_event(event)?;
+                        continue;
+                    }
+                },
+            };
+            let Some(message) = message else {
+                break;
+            };
+            let begin = *begin.get_or_insert(message.0);
+            let elapsed = (message.0 - begin).to_std().unwrap_or_default();
+            let (tstamp, role, payload) = message;
+            let idx = match role {
+                Role::Refe => REF,
+                Role::Test => TEST,
+            };
+            // A TESS4C line yields a sample per channel, counted once
+            if !matches!(payload, Payload::Json(ref json) if json.channel.is_some_and(|ch| ch > 1))
+            {
+                let key = stream_key(&payload).to_string();
+                self.decoding[idx].entry(key).or_default().accepted += 1;
+            }
+            let track = match role {
+                Role::Refe => Some(&mut self.refe),
+                Role::Test => self.test_index(&payload).map(|idx| &mut self.tests[idx]),
+            };
+            if let Some(track) = track {
+                if track.silent {
+                    info!(
+                        "{} photometer {} readings resumed, calibration resumes with fresh samples",
+                        LABEL[idx],
+                        track.label()
+                    );
+                    track.silent = false;
+                    self.resume_at = Some(tstamp);
                 }
+                track.last = Some(tstamp);
+                track.format.get_or_insert(payload.format());
+                track.buffer.keep_and_enqueue(tstamp, payload);
             }
-            if Instant::now().duration_since(begin) > Duration::from_millis(self.millis)
+            self.watch_tests(tstamp)?;
+            self.ready = self.all_ready();
+            if elapsed > Duration::from_millis(self.millis)
                 && self.ready
+                && self.resumed()
+                && self.all_sent_since(begin)
             {
-                self.refe.make_contiguous();
-                self.test.make_contiguous();
+                self.refe.buffer.make_contiguous();
                 info!("========================================================================");
-                let (r_freq, r_stdev, r_mag, r_win, r_dur) = self.refe.median();
-                let (t_freq, t_stdev, t_mag, t_win, t_dur) = self.test.median();
-                let mag_diff = r_mag - t_mag;
-                let zp = auxiliary::round(self.refe.info.zp + mag_diff, 2);
-                info!("ROUND {:02}: New ZP = {:0.2} = \u{0394}(ref-test) Mag ({:0.2}) + ZP Abs ({:0.2})",
-                    self.round, zp, mag_diff, self.refe.info.zp);
-                self.accumulate(REF, r_freq, r_stdev, r_mag, r_win, r_dur);
-                self.accumulate(TEST, t_freq, t_stdev, t_mag, t_win, t_dur);
-                self.zps.push(zp);
-                break;
+                let (r_freq, r_stdev, r_mag, r_win, r_dur) = self.refe.buffer.median();
+                self.refe.accumulate(r_freq, r_stdev, r_mag, r_win, r_dur);
+                let ref_zp = self.refe.buffer.info.zp;
+                for test in self.tests.iter_mut() {
+                    test.buffer.make_contiguous();
+                    let (t_freq, t_stdev, t_mag, t_win, t_dur) = test.buffer.median();
+                    let mag_diff = r_mag - t_mag;
+                    let zp = auxiliary::round(ref_zp + mag_diff, 2);
+                    info!("ROUND {:02}: {:9} New ZP = {:0.2} = \u{0394}(ref-test) Mag ({:0.2}) + ZP Abs ({:0.2})",
+                        self.round, test.label(), zp, mag_diff, ref_zp);
+                    test.accumulate(t_freq, t_stdev, t_mag, t_win, t_dur);
+                    test.zps.push(zp);
+                }
+                self.report_decoding();
+                return Ok(());
             }
         }
+        bail!(
+            "Photometer readings ended before completing round {}",
+            round
+        )
     }
 
-    fn summary(&self) -> f32 {
+    // Final zero point of every test photometer, by name and channel, and the summary rows to store
+    fn summary(&self) -> (Vec<ZeroPoint>, Vec<Summary>) {
         let offset_zp = self.info.offset;
         info!("########################################################################");
-        let best_zp = auxiliary::mode_or_median(&self.zps, 2, "ZP");
-        let final_zp = best_zp + offset_zp;
-        let best_ref_freq = auxiliary::mode_or_median(&self.freqs[REF], 3, "REF. Best freq.");
-        let best_test_freq = auxiliary::mode_or_median(&self.freqs[TEST], 3, "TEST Best freq.");
-        let best_ref_mag = auxiliary::magntude(best_ref_freq, 0.0, self.info.zp_fict);
-        let best_test_mag = auxiliary::magntude(best_test_freq, 0.0, self.info.zp_fict);
         info!(
             "Session = {}",
             self.session.to_rfc3339_opts(SecondsFormat::Secs, true)
         );
-        info!("Best ZP List is        {:?}", self.zps);
-        info!("Best REF. Freq List is {:?}", self.freqs[REF]);
-        info!("Best TEST Freq List is {:?}", self.freqs[TEST]);
+        let (best_ref_freq, ref_freq_method) =
+            auxiliary::mode_or_median(&self.refe.freqs, 3, "REF. Best freq.");
+        let best_ref_mag = auxiliary::magntude(best_ref_freq, 0.0, self.info.zp_fict);
+        info!("Best REF. Freq List is {:?}", self.refe.freqs);
         info!(
             "REF. Best Freq. = {:0.3} Hz, Mag = {:0.2}, Diff {:0.2}",
             best_ref_freq, best_ref_mag, 0.0
         );
-        info!(
-            "TEST. Best Freq. = {:0.3} Hz, Mag = {:0.2}, Diff {:0.2}",
-            best_test_freq, best_test_mag, 0.0
-        );
-        info!(
-            "Final TEST ZP ({:0.2}) = Best ZP ({:0.2}) + ZP offset ({:0.2})",
-            final_zp, best_zp, offset_zp
-        );
-        info!(
-            "Old TEST ZP = {:0.2}, NEW TEST ZP = {:0.2}",
-            self.test.info.zp, final_zp
-        );
+        let session = format_tstamp(&self.session);
+        let mut zero_points = Vec::with_capacity(self.tests.len());
+        let mut summaries = Vec::with_capacity(1 + self.tests.len());
+        summaries.push(self.summary_row(
+            &session,
+            REF,
+            &self.refe,
+            self.refe.buffer.info.zp,
+            None,
+            0.0,
+            best_ref_freq,
+            ref_freq_method,
+            best_ref_mag,
+        ));
+        for test in self.tests.iter() {
+            let name = test.label();
+            let (best_zp, zp_method) =
+                auxiliary::mode_or_median(&test.zps, 2, &format!("{} ZP", name));
+            let final_zp = best_zp + offset_zp;
+            let (best_test_freq, test_freq_method) =
+                auxiliary::mode_or_median(&test.freqs, 3, &format!("{} TEST Best freq.", name));
+            let best_test_mag = auxiliary::magntude(best_test_freq, 0.0, self.info.zp_fict);
+            info!("------------------------------------------------------------------------");
+            info!("{} Best ZP List is        {:?}", name, test.zps);
+            info!("{} Best TEST Freq List is {:?}", name, test.freqs);
+            info!(
+                "{} TEST. Best Freq. = {:0.3} Hz, Mag = {:0.2}, Diff {:0.2}",
+                name, best_test_freq, best_test_mag, 0.0
+            );
+            info!(
+                "{} Final TEST ZP ({:0.2}) = Best ZP ({:0.2}) + ZP offset ({:0.2})",
+                name, final_zp, best_zp, offset_zp
+            );
+            info!(
+                "{} Old TEST ZP = {:0.2}, NEW TEST ZP = {:0.2}",
+                name, test.buffer.info.zp, final_zp
+            );
+            summaries.push(self.summary_row(
+                &session,
+                TEST,
+                test,
+                final_zp,
+                Some(zp_method),
+                offset_zp,
+                best_test_freq,
+                test_freq_method,
+                best_test_mag,
+            ));
+            zero_points.push((test.name().to_string(), test.channel, final_zp));
+        }
         info!("########################################################################");
-        final_zp
+        (zero_points, summaries)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn summary_row(
+        &self,
+        session: &str,
+        idx: usize,
+        track: &Track,
+        zero_point: f32,
+        zero_point_method: Option<&str>,
+        offset: f32,
+        freq: f32,
+        freq_method: &str,
+        mag: f32,
+    ) -> Summary {
+        let author = self
+            .options
+            .author
+            .clone()
+            .unwrap_or_else(|| self.info.author.clone());
+        let info = &track.buffer.info;
+        Summary {
+            session: session.to_string(),
+            role: ROLE[idx].to_string(),
+            calibration: Some("AUTO".to_string()),
+            calversion: Some(env!("CARGO_PKG_VERSION").to_string()),
+            model: Some(info.model.clone()),
+            name: info.name.clone(),
+            channel: channel_key(track.channel),
+            mac: Some(info.mac.clone()),
+            firmware: Some(info.firmware.clone()),
+            sensor: Some(info.sensor.clone()),
+            prev_zp: Some(info.zp),
+            author: Some(author),
+            nrounds: Some(self.refe.freqs.len() as i32),
+            offset: Some(offset),
+            upd_flag: Some(0), // until the zero point is written, see Dao::zero_point_written
+            zero_point: Some(zero_point),
+            zero_point_method: zero_point_method.map(String::from),
+            freq: Some(freq),
+            freq_method: Some(freq_method.to_string()),
+            mag: Some(mag),
+            filter: Some(self.options.filter.clone()),
+            plug: Some(self.options.plug.clone()),
+            box_: Some(self.options.box_model.clone()),
+            collector: None,
+            comment: None,
+        }
+    }
+
+    fn samples(&self) -> Vec<models::Sample> {
+        let session = format_tstamp(&self.session);
+        let mut samples = self.refe.buffer.samples(&session, ROLE[REF], None);
+        for test in self.tests.iter() {
+            samples.extend(test.buffer.samples(&session, ROLE[TEST], test.channel));
+        }
+        samples
+    }
+
+    fn round_rows(&self, session: &str, idx: usize, track: &Track) -> Vec<Round> {
+        track
+            .tstamps
+            .iter()
+            .enumerate()
+            .map(|(i, (t0, t1))| Round {
+                session: session.to_string(),
+                round: (i + 1) as i32,
+                role: ROLE[idx].to_string(),
+                name: track.name().to_string(),
+                channel: channel_key(track.channel),
+                begin_tstamp: Some(format_tstamp(t0)),
+                end_tstamp: Some(format_tstamp(t1)),
+                central: Some(CENTRAL.to_string()),
+                freq: Some(track.freqs[i]),
+                stddev: Some(track.stdevs[i]),
+                mag: Some(track.mags[i]),
+                zp_fict: Some(self.info.zp_fict),
+                zero_point: track.zps.get(i).copied(),
+                nsamples: Some(track.buffer.initial_size as i32),
+                duration: Some(track.durs[i]),
+            })
+            .collect()
+    }
+
+    fn decoding_row(&self, session: &str, idx: usize, track: &Track) -> models::Decoding {
+        let stats = self.decode_stats(idx, track);
+        let mut row = decoding_row(session, idx, track.name(), &stats);
+        row.channel = channel_key(track.channel);
+        row.format = track.format.map(|format| format.to_string());
+        row
+    }
+
+    fn decodings(&self) -> Vec<models::Decoding> {
+        let session = format_tstamp(&self.session);
+        let mut decodings = vec![self.decoding_row(&session, REF, &self.refe)];
+        for test in self.tests.iter() {
+            decodings.push(self.decoding_row(&session, TEST, test));
+        }
+        // Lines naming no photometer are kept apart when several test photometers share the stream
+        if self.ntests > 1 {
+            if let Some(stats) = self.decoding[TEST].get("") {
+                decodings.push(decoding_row(&session, TEST, "", stats));
+            }
+        }
+        decodings
+    }
+
+    fn rounds(&self) -> Vec<Round> {
+        let session = format_tstamp(&self.session);
+        let mut rounds = self.round_rows(&session, REF, &self.refe);
+        for test in self.tests.iter() {
+            rounds.extend(self.round_rows(&session, TEST, test));
+        }
+        rounds
     }
 }
 
+fn decoding_row(session: &str, idx: usize, name: &str, stats: &DecodeStats) -> models::Decoding {
+    models::Decoding {
+        session: session.to_string(),
+        role: ROLE[idx].to_string(),
+        name: name.to_string(),
+        channel: 0,
+        accepted: Some(stats.accepted as i32),
+        malformed: Some(stats.malformed as i32),
+        duplicate: Some(stats.duplicate as i32),
+        bootstrap: Some(stats.bootstrap as i32),
+        out_of_range: Some(stats.out_of_range as i32),
+        unknown_format: Some(stats.unknown_format as i32),
+        out_of_order: Some(stats.out_of_order as i32),
+        lost: Some(stats.lost as i32),
+        reboots: Some(stats.reboots as i32),
+        format: None,
+    }
+}
+
+// Returns the final zero point of every test photometer, by name and channel
 pub async fn calibration_task(
     pool: Pool,
-    session: Timestamp,
     chan: Receiver<Sample>,
-    capacity: usize,
-    nrounds: usize,
-    millis: u64,
+    events: Receiver<Event>,
     ref_info: Info,
     test_info: Info,
-) -> Result<f32> {
+    rounds: RoundOptions,
+    options: SessionOptions,
+) -> Result<Vec<ZeroPoint>> {
     let dao = dao::Dao::new(pool);
     let cal_info = dao.read_config().await?;
-    let mut calib = Calibration::new(
-        capacity, session, chan, nrounds, millis, ref_info, test_info, cal_info,
-    );
+    let persist = options.persist;
+    let nrounds = rounds.nrounds;
+    let mut calib = Calibration::new(chan, events, ref_info, test_info, cal_info, rounds, options);
     for i in 1..=nrounds {
-        calib.one_round(i).await;
+        calib.one_round(i).await?;
+    }
+    let (zero_points, summaries) = calib.summary();
+    if persist {
+        dao.write_calibration(
+            calib.samples(),
+            calib.rounds(),
+            summaries,
+            calib.decodings(),
+        )
+        .await?;
+    } else {
+        info!("Test calibration, results not saved to database");
     }
-    let zp = calib.summary();
     info!("Calibration task finished");
-    Ok(zp)
+    Ok(zero_points)
 }
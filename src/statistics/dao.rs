@@ -1,9 +1,68 @@
-use super::{CalibrationInfo, Pool};
-use crate::database::{models::Config, Db};
+use super::{CalibrationInfo, Payload, Pool, Timestamp};
+use crate::database::{
+    models::{self, Config, Round, Summary},
+    Db, DbConnection,
+};
 use anyhow::Result;
 use diesel::prelude::*;
 use tokio::task;
-use tracing::{debug, error};
+use tracing::{debug, error, info};
+
+// Same timestamp format as the former Python zptess database entries
+const TSTAMP_FMT: &str = "%Y-%m-%dT%H:%M:%S";
+// Samples need sub-second resolution as the timestamp is part of the primary key
+const TSTAMP_MILLIS_FMT: &str = "%Y-%m-%dT%H:%M:%S%.3f";
+// Keeps each INSERT well below the SQLite maximum number of host parameters
+const SAMPLES_CHUNK: usize = 100;
+
+pub fn format_tstamp(tstamp: &Timestamp) -> String {
+    tstamp.format(TSTAMP_FMT).to_string()
+}
+
+pub fn format_tstamp_millis(tstamp: &Timestamp) -> String {
+    tstamp.format(TSTAMP_MILLIS_FMT).to_string()
+}
+
+// Single channel photometers are stored as channel 0, as the channel is part of the primary key
+pub fn channel_key(channel: Option<u8>) -> i32 {
+    channel.map(i32::from).unwrap_or_default()
+}
+
+pub fn sample_row(
+    session: &str,
+    role: &str,
+    name: &str,
+    channel: Option<u8>,
+    tstamp: &Timestamp,
+    payload: &Payload,
+) -> models::Sample {
+    let (freq, seq, temp_box) = match payload {
+        Payload::Json(p) => (p.freq, p.udp.map(|udp| udp as i32), p.tamb),
+        Payload::Cristogg(p) => (p.freq, None, Some(p.tbox)),
+    };
+    models::Sample {
+        tstamp: format_tstamp_millis(tstamp),
+        role: role.to_string(),
+        name: name.to_string(),
+        channel: channel_key(channel),
+        session: session.to_string(),
+        freq: Some(freq),
+        seq,
+        temp_box,
+    }
+}
+
+// Samples are keyed by session, role, timestamp, photometer name and channel,
+// so that neither photometers calibrated together nor replays of one capture clash
+fn insert_samples(conn: &mut DbConnection, samples: &[models::Sample]) -> QueryResult<()> {
+    use crate::database::schema::samples_t;
+    for chunk in samples.chunks(SAMPLES_CHUNK) {
+        diesel::insert_into(samples_t::table)
+            .values(chunk)
+            .execute(conn)?;
+    }
+    Ok(())
+}
 
 pub struct Dao {
     pool: Pool,
@@ -39,4 +98,68 @@ impl Dao {
         //info!("{info:#?}");
         Ok(info)
     }
+
+    // Samples, rounds, summaries and decoding statistics of a calibration session
+    // are written all or nothing
+    pub async fn write_calibration(
+        &self,
+        samples: Vec<models::Sample>,
+        rounds: Vec<Round>,
+        summaries: Vec<Summary>,
+        decodings: Vec<models::Decoding>,
+    ) -> Result<()> {
+        use crate::database::schema::{decoding_t, rounds_t, summary_t};
+        let mut conn1 = self.pool.get()?;
+        let (nsamples, nrounds, nsummaries) = (samples.len(), rounds.len(), summaries.len());
+        task::spawn_blocking(move || {
+            conn1.transaction::<_, diesel::result::Error, _>(|conn| {
+                insert_samples(conn, &samples)?;
+                let sql = diesel::insert_into(rounds_t::table).values(&rounds);
+                debug!("{:?}", diesel::debug_query::<Db, _>(&sql).to_string());
+                sql.execute(conn)?;
+                let sql = diesel::insert_into(summary_t::table).values(&summaries);
+                debug!("{:?}", diesel::debug_query::<Db, _>(&sql).to_string());
+                sql.execute(conn)?;
+                let sql = diesel::insert_into(decoding_t::table).values(&decodings);
+                debug!("{:?}", diesel::debug_query::<Db, _>(&sql).to_string());
+                sql.execute(conn)?;
+                Ok(())
+            })
+        })
+        .await??;
+        info!(
+            "Saved {} samples, {} rounds and {} summaries to database",
+            nsamples, nrounds, nsummaries
+        );
+        Ok(())
+    }
+
+    // The summary of a test photometer is flagged once its new zero point is written to it
+    pub async fn zero_point_written(
+        &self,
+        session: &Timestamp,
+        name: &str,
+        channel: Option<u8>,
+    ) -> Result<()> {
+        use crate::database::schema::summary_t;
+        let mut conn1 = self.pool.get()?;
+        let sql = diesel::update(summary_t::table)
+            .filter(summary_t::session.eq(format_tstamp(session)))
+            .filter(summary_t::role.eq("test"))
+            .filter(summary_t::name.eq(name.to_string()))
+            .filter(summary_t::channel.eq(channel_key(channel)))
+            .set(summary_t::upd_flag.eq(1));
+        debug!("{:?}", diesel::debug_query::<Db, _>(&sql).to_string());
+        task::spawn_blocking(move || sql.execute(&mut conn1)).await??;
+        Ok(())
+    }
+
+    pub async fn write_samples(&self, samples: Vec<models::Sample>) -> Result<()> {
+        let mut conn1 = self.pool.get()?;
+        task::spawn_blocking(move || {
+            conn1.transaction::<_, diesel::result::Error, _>(|conn| insert_samples(conn, &samples))
+        })
+        .await??;
+        Ok(())
+    }
 }
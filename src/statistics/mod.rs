@@ -2,25 +2,31 @@ pub mod auxiliary;
 pub mod calibration;
 pub mod dao;
 pub mod readings;
+pub mod recorder;
 
+use crate::database::models;
+use crate::photometer::profile::Profile;
 use crate::Timestamp;
 use statistical;
 use std::collections::VecDeque;
+use std::time::Duration;
 use tracing::info;
 // Re-exports for the submodules
 pub use crate::database::Pool;
 pub use crate::photometer::discovery::Info;
-pub use crate::photometer::payload::Payload;
+pub use crate::photometer::payload::{Json, Payload};
 pub use crate::Sample;
 // Re-exports for the other modules
 pub use calibration::calibration_task;
 pub use readings::reading_task;
+pub use recorder::recording_task;
 
 type PayloadQueue = VecDeque<Payload>;
 type TimestampQueue = VecDeque<Timestamp>;
 pub type TimeWindow = (Timestamp, Timestamp); // t0, t1 time window
 
 pub const LABEL: [&str; 2] = ["REF.", "TEST"];
+pub const ROLE: [&str; 2] = ["ref", "test"]; // role names as stored in the database
 pub const REF: usize = 0; // index into array
 pub const TEST: usize = 1; // index into array
 
@@ -50,28 +56,72 @@ impl CalibrationInfo {
 }
 
 pub struct SamplesBuffer {
-    label: &'static str,
+    label: String,
     initial_size: usize,
     read_q: PayloadQueue,
     time_q: TimestampQueue,
+    history: Vec<(Timestamp, Payload)>, // every sample since the session start, to be stored
     ready: bool,
     info: Info,
     zp_fict: f32,
 }
 
+// Calibration session data coming from the command line
+// that does not take part in the statistics computation
+#[derive(Debug, Default)]
+pub struct SessionOptions {
+    pub session: Timestamp,     // calibration start, identifies its database rows
+    pub author: Option<String>, // overrides the config_t author if given
+    pub filter: String,
+    pub plug: String,
+    pub box_model: String,
+    pub update: bool,           // zero point is to be written to the test photometer
+    pub persist: bool,          // save results to database
+    pub pause_on_silence: bool, // instead of aborting the calibration
+}
+
+// How the calibration rounds are taken
+#[derive(Debug, Clone)]
+pub struct RoundOptions {
+    pub window: usize,             // samples in every round, usually 9
+    pub nrounds: usize,            // usually 5
+    pub ntests: usize,             // test photometers (or channels) calibrated at once
+    pub millis: u64,               // between rounds, usually 5000
+    pub profile: &'static Profile, // of the test photometers
+    pub silence: Duration,         // without samples before a test photometer is silent
+}
+
 impl SamplesBuffer {
-    fn new(initial_size: usize, info: Info, label: &'static str, zp_fict: f32) -> Self {
+    fn new(initial_size: usize, info: Info, label: &str, zp_fict: f32) -> Self {
         Self {
             read_q: PayloadQueue::with_capacity(initial_size),
             time_q: TimestampQueue::with_capacity(initial_size),
+            history: Vec::new(),
             ready: false,
             info,
-            label,
+            label: label.to_string(),
             initial_size,
             zp_fict,
         }
     }
 
+    // Every channel of a multi-channel photometer has its own buffer and zero point
+    fn for_test(initial_size: usize, mut info: Info, payload: &Json, zp_fict: f32) -> Self {
+        let label = match payload.channel {
+            Some(channel) => {
+                info.zp = info
+                    .channel_zps
+                    .get(channel as usize - 1)
+                    .copied()
+                    .or(payload.ZP)
+                    .unwrap_or_default();
+                format!("CH.{}", channel)
+            }
+            None => LABEL[TEST].to_string(),
+        };
+        Self::new(initial_size, info, &label, zp_fict)
+    }
+
     fn enqueue(&mut self, tstamp: Timestamp, payload: Payload) {
         let length = self.read_q.len();
         let capacity = self.read_q.capacity();
@@ -94,13 +144,9 @@ impl SamplesBuffer {
         }
     }
 
-    fn possibly_enqueue(&mut self, tstamp: Timestamp, payload: Payload, accumulate: bool) {
-        // let the read_q grow and grow so we can save all samples
-        if accumulate {
-            self.read_q.push_back(payload);
-            self.time_q.push_back(tstamp);
-            return;
-        }
+    // The round window only holds the latest samples, all of them are kept apart
+    fn keep_and_enqueue(&mut self, tstamp: Timestamp, payload: Payload) {
+        self.history.push((tstamp, payload.clone()));
         self.enqueue(tstamp, payload);
     }
 
@@ -109,6 +155,22 @@ impl SamplesBuffer {
         self.time_q.make_contiguous();
     }
 
+    // Every sample received, ready to be stored in the database
+    fn samples(&self, session: &str, role: &str, channel: Option<u8>) -> Vec<models::Sample> {
+        self.history
+            .iter()
+            .map(|(tstamp, payload)| {
+                dao::sample_row(session, role, &self.info.name, channel, tstamp, payload)
+            })
+            .collect()
+    }
+
+    // Timestamp of the oldest sample used by median()
+    fn window_start(&self) -> Option<Timestamp> {
+        let from = self.time_q.len().checked_sub(self.initial_size)?;
+        self.time_q.get(from).copied()
+    }
+
     fn speed(&self) -> f32 {
         let (tstamps_slice, _) = self.time_q.as_slices();
         let t0 = tstamps_slice.first().expect("t0 timestamp expected");
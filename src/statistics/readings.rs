@@ -1,13 +1,19 @@
 use super::{Info, Payload, Pool, Sample, SamplesBuffer, LABEL, REF, TEST};
 use crate::statistics::dao;
+use crate::statistics::recorder::Recorder;
+use crate::Role;
 use anyhow::Result;
 use std::cmp;
 use tokio::sync::mpsc::Receiver;
 
 pub struct Reading {
     refe: Option<SamplesBuffer>, // may not be present if reading the test photometer only
-    test: Option<SamplesBuffer>, // may not be present if reading the ref photometer only
-    channel: Receiver<Sample>,   // where to receive the samples from photometer tasks
+    test_info: Option<Info>,     // may not be present if reading the ref photometer only
+    tests: Vec<(Option<u8>, SamplesBuffer)>, // one per channel of the test photometer
+    window: usize,
+    zp_fict: f32,
+    channel: Receiver<Sample>, // where to receive the samples from photometer tasks
+    recorder: Option<Recorder>, // where to hand over samples to be saved in the database
 }
 
 impl Reading {
@@ -17,56 +23,94 @@ impl Reading {
         ref_info: Option<Info>,
         test_info: Option<Info>,
         zp_fict: f32,
+        recorder: Option<Recorder>,
     ) -> Self {
         let rbuf = ref_info.map(|info| SamplesBuffer::new(window, info, LABEL[REF], zp_fict));
-        let tbuf = test_info.map(|info| SamplesBuffer::new(window, info, LABEL[TEST], zp_fict));
         Self {
             channel,
             refe: rbuf,
-            test: tbuf,
+            test_info,
+            tests: Vec::new(),
+            window,
+            zp_fict,
+            recorder,
         }
     }
 
+    // Test samples go to the buffer of their channel, created on its first sample
+    fn enqueue_test(&mut self, sample: Sample) -> usize {
+        let (tstamp, _, payload) = sample;
+        let channel = match payload {
+            Payload::Json(ref json) => json.channel,
+            Payload::Cristogg(_) => None,
+        };
+        let idx = match self.tests.iter().position(|(c, _)| *c == channel) {
+            Some(idx) => idx,
+            None => {
+                let info = self.test_info.clone().expect("test photometer info");
+                let buffer = match payload {
+                    Payload::Json(ref json) => {
+                        SamplesBuffer::for_test(self.window, info, json, self.zp_fict)
+                    }
+                    Payload::Cristogg(_) => {
+                        SamplesBuffer::new(self.window, info, LABEL[TEST], self.zp_fict)
+                    }
+                };
+                self.tests.push((channel, buffer));
+                self.tests.len() - 1
+            }
+        };
+        self.tests[idx].1.enqueue(tstamp, payload);
+        idx
+    }
+
     async fn reading_both(&mut self) {
         let mut i: u8 = 0;
         while let Some(message) = self.channel.recv().await {
-            match message {
-                (tstamp, Payload::Json(reading)) => {
-                    if let Some(ref mut queue) = self.test {
-                        queue.enqueue(tstamp, Payload::Json(reading));
-                    }
+            if let Some(ref recorder) = self.recorder {
+                recorder.record(&message).await;
+            }
+            match message.1 {
+                Role::Refe => {
+                    let (tstamp, _, payload) = message;
+                    self.refe.as_mut().unwrap().enqueue(tstamp, payload);
                 }
-                (tstamp, Payload::Cristogg(reading)) => {
-                    if let Some(ref mut queue) = self.refe {
-                        queue.enqueue(tstamp, Payload::Cristogg(reading));
-                    }
+                Role::Test => {
+                    self.enqueue_test(message);
                 }
             }
-            let test_queue = self.test.as_mut().unwrap();
             let refe_queue = self.refe.as_mut().unwrap();
-            if test_queue.ready && refe_queue.ready {
+            if self.tests.is_empty()
+                || !refe_queue.ready
+                || self.tests.iter().any(|(_, t)| !t.ready)
+            {
+                continue;
+            }
+            refe_queue.make_contiguous();
+            for (_, test_queue) in self.tests.iter_mut() {
                 test_queue.make_contiguous();
-                refe_queue.make_contiguous();
-                let speed = refe_queue.speed() / test_queue.speed();
-                let n = (if speed < 1.0 { 1.0 / speed } else { speed }).round() as u8;
-                if i == 0 {
-                    refe_queue.median();
+            }
+            // Every channel of the test photometer is read at the same pace
+            let speed = refe_queue.speed() / self.tests[0].1.speed();
+            let n = (if speed < 1.0 { 1.0 / speed } else { speed }).round() as u8;
+            if i == 0 {
+                refe_queue.median();
+                for (_, test_queue) in self.tests.iter() {
                     test_queue.median();
                 }
-                i = (i + 1) % n;
             }
+            i = (i + 1) % n;
         }
     }
 
-    async fn reading_single(&mut self) {
+    async fn reading_ref(&mut self) {
         let mut i: u8 = 0;
-        let queue = if self.refe.is_some() {
-            self.refe.as_mut().unwrap()
-        } else {
-            self.test.as_mut().unwrap()
-        };
+        let queue = self.refe.as_mut().unwrap();
         while let Some(message) = self.channel.recv().await {
-            let (tstamp, payload) = message;
+            if let Some(ref recorder) = self.recorder {
+                recorder.record(&message).await;
+            }
+            let (tstamp, _, payload) = message;
             queue.enqueue(tstamp, payload);
             if queue.ready {
                 queue.make_contiguous();
@@ -79,12 +123,32 @@ impl Reading {
         }
     }
 
+    async fn reading_test(&mut self) {
+        let mut ticks: Vec<u8> = Vec::new(); // one per channel
+        while let Some(message) = self.channel.recv().await {
+            if let Some(ref recorder) = self.recorder {
+                recorder.record(&message).await;
+            }
+            let idx = self.enqueue_test(message);
+            ticks.resize(self.tests.len(), 0);
+            let queue = &mut self.tests[idx].1;
+            if queue.ready {
+                queue.make_contiguous();
+                let n = cmp::max((queue.speed()).round() as u8, 1);
+                if ticks[idx] == 0 {
+                    queue.median();
+                }
+                ticks[idx] = (ticks[idx] + 1) % n;
+            }
+        }
+    }
+
     async fn reading(&mut self) {
-        if self.refe.is_some() && self.test.is_some() {
-            self.reading_both().await;
-            return;
+        match (self.refe.is_some(), self.test_info.is_some()) {
+            (true, true) => self.reading_both().await,
+            (true, false) => self.reading_ref().await,
+            (false, _) => self.reading_test().await,
         }
-        self.reading_single().await;
     }
 }
 
@@ -94,10 +158,18 @@ pub async fn reading_task(
     capacity: usize,
     ref_info: Option<Info>,
     test_info: Option<Info>,
+    recorder: Option<Recorder>,
 ) -> Result<()> {
     let dao = dao::Dao::new(pool);
     let cal_info = dao.read_config().await?;
-    let mut stats = Reading::new(capacity, chan, ref_info, test_info, cal_info.zp_fict);
+    let mut stats = Reading::new(
+        capacity,
+        chan,
+        ref_info,
+        test_info,
+        cal_info.zp_fict,
+        recorder,
+    );
     stats.reading().await;
     Ok(())
 }
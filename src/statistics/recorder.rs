@@ -0,0 +1,80 @@
+use super::{Payload, Pool, Sample, Timestamp, REF, ROLE, TEST};
+use crate::statistics::dao::{self, format_tstamp};
+use crate::Role;
+use anyhow::Result;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::time::{self, Duration};
+use tracing::{error, info};
+
+const BATCH_SIZE: usize = 50; // Samples written to the database in one go
+const FLUSH_PERIOD: u64 = 5; // Seconds before writing an incomplete batch
+
+// Handle used by the statistics tasks to hand samples over to the recording task.
+// It waits for the recording task to catch up rather than losing samples.
+pub struct Recorder {
+    channel: Sender<Sample>,
+}
+
+impl Recorder {
+    pub fn new(channel: Sender<Sample>) -> Self {
+        Self { channel }
+    }
+
+    pub async fn record(&self, sample: &Sample) {
+        // A closed channel means the recording task is gone, nothing left to do
+        let _ = self.channel.send(sample.clone()).await;
+    }
+}
+
+async fn flush(dao: &dao::Dao, batch: &mut Vec<crate::database::models::Sample>) {
+    if batch.is_empty() {
+        return;
+    }
+    let samples = std::mem::take(batch);
+    let n = samples.len();
+    match dao.write_samples(samples).await {
+        Ok(_) => info!("Recorded {} samples to database", n),
+        Err(e) => error!("Recording {} samples to database: {e:?}", n),
+    }
+}
+
+// Writes in batches every sample received until the channel is closed.
+// Readings without a photometer name are recorded under the name given for their role.
+pub async fn recording_task(
+    pool: Pool,
+    session: Timestamp,
+    mut chan: Receiver<Sample>,
+    names: [String; 2],
+) -> Result<()> {
+    let dao = dao::Dao::new(pool);
+    let session = format_tstamp(&session);
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+    let mut ticker = time::interval(Duration::from_secs(FLUSH_PERIOD));
+    info!("Recording samples under read session {}", session);
+    loop {
+        tokio::select! {
+            message = chan.recv() => match message {
+                Some((tstamp, role, payload)) => {
+                    let idx = match role {
+                        Role::Refe => REF,
+                        Role::Test => TEST,
+                    };
+                    let (name, channel) = match payload {
+                        Payload::Json(ref json) => (json.name.as_str(), json.channel),
+                        Payload::Cristogg(_) => (names[idx].as_str(), None),
+                    };
+                    let row = dao::sample_row(&session, ROLE[idx], name, channel, &tstamp, &payload);
+                    batch.push(row);
+                    if batch.len() >= BATCH_SIZE {
+                        flush(&dao, &mut batch).await;
+                    }
+                }
+                None => break,
+            },
+            _ = ticker.tick() => flush(&dao, &mut batch).await,
+        }
+    }
+    flush(&dao, &mut batch).await;
+    info!("Recording task finished");
+    Ok(())
+}
@@ -0,0 +1,445 @@
+// Calibration sessions fed with readings as the reading tasks would send them
+
+mod common;
+
+use chrono::prelude::*;
+use common::{CaptureFile, TempDb};
+use diesel::prelude::*;
+use tokio::sync::mpsc::{self, Sender};
+use tokio::task::JoinHandle;
+use zptess::database;
+use zptess::database::schema::{decoding_t, rounds_t, samples_t, summary_t};
+use zptess::photometer::discovery::Info;
+use zptess::photometer::payload::{Json, Payload};
+use zptess::photometer::profile::{REFERENCE, TESSW};
+use zptess::photometer::transport::Endpoint;
+use zptess::photometer::{reading_task, Event, Status};
+use zptess::statistics::calibration::ZeroPoint;
+use zptess::statistics::dao::{self, Dao};
+use zptess::statistics::{self, RoundOptions, SessionOptions};
+use zptess::{Role, Sample};
+
+const NROUNDS: usize = 3;
+const REF_ZP: f32 = 20.44;
+const SILENCE: u64 = 10; // seconds without samples before a test photometer is silent
+
+fn json(name: &str, freq: f32) -> Json {
+    let line = format!(
+        "{{\"udp\":1,\"name\":\"{}\",\"freq\":{},\"ZP\":{}}}",
+        name, freq, REF_ZP
+    );
+    serde_json::from_str::<Json>(&line).unwrap()
+}
+
+fn session_start() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2026, 10, 18, 20, 0, 0).unwrap()
+}
+
+// A calibration task with its samples and events channels
+struct Session {
+    samples: Sender<Sample>,
+    events: Sender<Event>,
+    task: JoinHandle<anyhow::Result<Vec<ZeroPoint>>>,
+}
+
+impl Session {
+    fn start(db: &TempDb, ntests: usize, persist: bool) -> Self {
+        let options = SessionOptions {
+            persist,
+            ..Default::default()
+        };
+        Self::start_with(db, ntests, options)
+    }
+
+    fn start_with(db: &TempDb, ntests: usize, options: SessionOptions) -> Self {
+        let (samples, rx) = mpsc::channel(32);
+        let (events, ev_rx) = mpsc::channel(8);
+        let rounds = RoundOptions {
+            window: 9,
+            nrounds: NROUNDS,
+            ntests,
+            millis: 5000,
+            profile: &TESSW,
+            silence: tokio::time::Duration::from_secs(SILENCE),
+        };
+        let options = SessionOptions {
+            session: session_start(),
+            ..options
+        };
+        let task = tokio::spawn(statistics::calibration_task(
+            database::get_connection_pool(db.url()),
+            rx,
+            ev_rx,
+            Info::from_reading(&REFERENCE, &json("stars3", 0.0)),
+            Info::from_reading(&TESSW, &json("stars1", 0.0)),
+            rounds,
+            options,
+        ));
+        Self {
+            samples,
+            events,
+            task,
+        }
+    }
+
+    // A reading of every photometer each second, until the calibration is over or time runs out
+    async fn feed(&self, photometers: &[(Role, &str, f32)], secs: i64) {
+        self.feed_from(photometers, 0, secs).await
+    }
+
+    async fn feed_from(&self, photometers: &[(Role, &str, f32)], from: i64, secs: i64) {
+        for sec in from..from + secs {
+            let tstamp = session_start() + chrono::Duration::seconds(sec);
+            for (role, name, freq) in photometers {
+                let payload = Payload::Json(json(name, *freq));
+                if self.samples.send((tstamp, *role, payload)).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn end(self) -> anyhow::Result<Vec<ZeroPoint>> {
+        drop(self.samples);
+        let result = self.task.await.unwrap();
+        drop(self.events);
+        result
+    }
+}
+
+fn zero_point(zero_points: &[ZeroPoint], name: &str) -> f32 {
+    zero_points
+        .iter()
+        .find(|(n, _, _)| n == name)
+        .unwrap_or_else(|| panic!("no zero point for {}", name))
+        .2
+}
+
+// Twice the frequency is about 0.75 magnitudes brighter
+#[tokio::test]
+async fn interleaved_test_photometers_get_their_own_results() {
+    let db = TempDb::new("calibration-two-tests");
+    let session = Session::start(&db, 2, true);
+    let photometers = [
+        (Role::Refe, "stars3", 10.0),
+        (Role::Test, "stars1", 10.0),
+        (Role::Test, "stars2", 20.0),
+    ];
+    session.feed(&photometers, 60).await;
+    let zero_points = session.end().await.unwrap();
+    assert_eq!(zero_points.len(), 2);
+    assert!((zero_point(&zero_points, "stars1") - REF_ZP).abs() < 0.005);
+    assert!((zero_point(&zero_points, "stars2") - (REF_ZP + 0.75)).abs() < 0.005);
+
+    let mut conn = database::get_connection_pool(db.url()).get().unwrap();
+    for (role, name, _) in photometers {
+        let role = if role == Role::Refe { "ref" } else { "test" };
+        let rounds = rounds_t::table
+            .filter(rounds_t::role.eq(role))
+            .filter(rounds_t::name.eq(name))
+            .count()
+            .get_result::<i64>(&mut conn)
+            .unwrap();
+        assert_eq!(rounds, NROUNDS as i64, "{} rounds", name);
+    }
+    let mut summaries = summary_t::table
+        .filter(summary_t::role.eq("test"))
+        .select((summary_t::name, summary_t::zero_point))
+        .load::<(String, Option<f32>)>(&mut conn)
+        .unwrap();
+    summaries.sort_by(|a, b| a.0.cmp(&b.0));
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(summaries[0].0, "stars1");
+    assert_eq!(summaries[1].0, "stars2");
+    assert_eq!(summaries[0].1, Some(zero_point(&zero_points, "stars1")));
+    assert_eq!(summaries[1].1, Some(zero_point(&zero_points, "stars2")));
+}
+
+// Samples already queued are used, then the calibration gives up naming the photometer
+#[tokio::test]
+async fn ended_readings_abort_the_calibration() {
+    let db = TempDb::new("calibration-ended");
+    let session = Session::start(&db, 1, true);
+    session
+        .feed(
+            &[(Role::Refe, "stars3", 10.0), (Role::Test, "stars1", 10.0)],
+            3,
+        )
+        .await;
+    let ended = Event {
+        tstamp: session_start(),
+        role: Role::Test,
+        status: Status::Ended(Some("Connection reset by peer".to_string())),
+    };
+    session.events.send(ended).await.unwrap();
+    let e = session.end().await.unwrap_err().to_string();
+    assert!(e.contains("TEST photometer stars1"), "{}", e);
+    assert!(e.contains("Connection reset by peer"), "{}", e);
+}
+
+const TWO_TESTS: [(Role, &str, f32); 3] = [
+    (Role::Refe, "stars3", 10.0),
+    (Role::Test, "stars1", 10.0),
+    (Role::Test, "stars2", 20.0),
+];
+
+// Readings of stars2 go on while those of stars1, sharing its stream, stop
+#[tokio::test]
+async fn silent_test_photometers_abort_the_calibration() {
+    let db = TempDb::new("calibration-silent-test");
+    let session = Session::start(&db, 2, false);
+    session.feed(&TWO_TESTS, 12).await;
+    let others = [TWO_TESTS[0], TWO_TESTS[2]];
+    session.feed_from(&others, 12, 60).await;
+    let e = session.end().await.unwrap_err().to_string();
+    assert!(e.contains("TEST photometer stars1 silent"), "{}", e);
+}
+
+// Rounds wait for stars1 to be back, then only use its fresh samples
+#[tokio::test]
+async fn silent_test_photometers_pause_the_calibration() {
+    let db = TempDb::new("calibration-paused-test");
+    let options = SessionOptions {
+        persist: true,
+        pause_on_silence: true,
+        ..Default::default()
+    };
+    let session = Session::start_with(&db, 2, options);
+    session.feed(&TWO_TESTS, 12).await;
+    let others = [TWO_TESTS[0], TWO_TESTS[2]];
+    session.feed_from(&others, 12, 60).await;
+    let back = [TWO_TESTS[0], (Role::Test, "stars1", 20.0), TWO_TESTS[2]];
+    session.feed_from(&back, 72, 60).await;
+    session.end().await.unwrap();
+    let mut conn = database::get_connection_pool(db.url()).get().unwrap();
+    let rounds = rounds_t::table
+        .filter(rounds_t::name.eq("stars1"))
+        .order(rounds_t::round)
+        .select((rounds_t::begin_tstamp, rounds_t::freq))
+        .load::<(Option<String>, Option<f32>)>(&mut conn)
+        .unwrap();
+    assert_eq!(rounds.len(), NROUNDS);
+    let (begin, freq) = rounds.last().unwrap();
+    let back_at = session_start() + chrono::Duration::seconds(72);
+    assert!(
+        begin.as_deref() >= Some(&*dao::format_tstamp(&back_at)),
+        "{:?}",
+        begin
+    );
+    assert_eq!(*freq, Some(20.0));
+}
+
+fn upd_flags(db: &TempDb) -> Vec<(String, Option<i32>)> {
+    let mut conn = database::get_connection_pool(db.url()).get().unwrap();
+    summary_t::table
+        .order(summary_t::role)
+        .select((summary_t::role, summary_t::upd_flag))
+        .load::<(String, Option<i32>)>(&mut conn)
+        .unwrap()
+}
+
+// Nothing is flagged as updated until the zero point is actually written
+#[tokio::test]
+async fn summaries_are_flagged_once_written() {
+    let db = TempDb::new("calibration-upd-flag");
+    let session = Session::start(&db, 1, true);
+    session
+        .feed(
+            &[(Role::Refe, "stars3", 10.0), (Role::Test, "stars1", 10.0)],
+            60,
+        )
+        .await;
+    session.end().await.unwrap();
+    let flags = upd_flags(&db);
+    assert_eq!(flags, [("ref".into(), Some(0)), ("test".into(), Some(0))]);
+    let dao = Dao::new(database::get_connection_pool(db.url()));
+    dao.zero_point_written(&session_start(), "stars1", None)
+        .await
+        .unwrap();
+    let flags = upd_flags(&db);
+    assert_eq!(flags, [("ref".into(), Some(0)), ("test".into(), Some(1))]);
+}
+
+const SINGLE: [(Role, &str, f32); 2] = [(Role::Refe, "stars3", 10.0), (Role::Test, "stars1", 10.0)];
+
+// Every round of every photometer is stored, together with both summaries
+#[tokio::test]
+async fn rounds_and_summaries_are_stored() {
+    let db = TempDb::new("calibration-rounds");
+    let session = Session::start(&db, 1, true);
+    session.feed(&SINGLE, 60).await;
+    let zero_points = session.end().await.unwrap();
+    let mut conn = database::get_connection_pool(db.url()).get().unwrap();
+    let rounds = rounds_t::table
+        .order((rounds_t::role, rounds_t::round))
+        .select((rounds_t::role, rounds_t::round, rounds_t::name))
+        .load::<(String, i32, String)>(&mut conn)
+        .unwrap();
+    let expected = ["ref", "test"]
+        .into_iter()
+        .flat_map(|role| (1..=NROUNDS as i32).map(move |round| (role, round)))
+        .map(|(role, round)| {
+            let name = if role == "ref" { "stars3" } else { "stars1" };
+            (role.to_string(), round, name.to_string())
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(rounds, expected);
+    let summaries = summary_t::table
+        .order(summary_t::role)
+        .select((
+            summary_t::role,
+            summary_t::name,
+            summary_t::nrounds,
+            summary_t::zero_point,
+        ))
+        .load::<(String, String, Option<i32>, Option<f32>)>(&mut conn)
+        .unwrap();
+    assert_eq!(summaries.len(), 2);
+    assert_eq!(summaries[0].0, "ref");
+    assert_eq!(summaries[0].1, "stars3");
+    assert_eq!(summaries[0].3, Some(REF_ZP));
+    assert_eq!(summaries[1].0, "test");
+    assert_eq!(summaries[1].1, "stars1");
+    assert_eq!(summaries[1].3, Some(zero_point(&zero_points, "stars1")));
+    for summary in summaries.iter() {
+        assert_eq!(summary.2, Some(NROUNDS as i32));
+    }
+}
+
+// Reference samples received while the test photometer buffer fills are stored too
+#[tokio::test]
+async fn every_sample_is_stored() {
+    let db = TempDb::new("calibration-samples");
+    let session = Session::start(&db, 1, true);
+    session.feed(&SINGLE[..1], 20).await;
+    session.feed_from(&SINGLE, 20, 60).await;
+    session.end().await.unwrap();
+    let mut conn = database::get_connection_pool(db.url()).get().unwrap();
+    let mut counts = Vec::new();
+    for role in ["ref", "test"] {
+        let tstamps = samples_t::table
+            .filter(samples_t::role.eq(role))
+            .order(samples_t::tstamp)
+            .select(samples_t::tstamp)
+            .load::<String>(&mut conn)
+            .unwrap();
+        counts.push(tstamps.len());
+        if role == "ref" {
+            assert_eq!(tstamps[0], dao::format_tstamp_millis(&session_start()));
+        }
+    }
+    // The calibration may be over between the reference and test samples of a second
+    assert!((20..=21).contains(&(counts[0] - counts[1])), "{:?}", counts);
+}
+
+// A test calibration computes the zero point and leaves the database alone
+#[tokio::test]
+async fn test_calibrations_store_nothing() {
+    let db = TempDb::new("calibration-not-persisted");
+    let session = Session::start(&db, 1, false);
+    session.feed(&SINGLE, 60).await;
+    let zero_points = session.end().await.unwrap();
+    assert!((zero_point(&zero_points, "stars1") - REF_ZP).abs() < 0.005);
+    let mut conn = database::get_connection_pool(db.url()).get().unwrap();
+    let counts = [
+        samples_t::table.count().get_result::<i64>(&mut conn),
+        rounds_t::table.count().get_result::<i64>(&mut conn),
+        summary_t::table.count().get_result::<i64>(&mut conn),
+        decoding_t::table.count().get_result::<i64>(&mut conn),
+    ];
+    for count in counts {
+        assert_eq!(count.unwrap(), 0);
+    }
+}
+
+// A saved calibration of the photometers in the capture file, as if started at the given time
+async fn replayed_calibration(db: &TempDb, file: &CaptureFile, session: DateTime<Utc>) {
+    let endpoint = Endpoint::Replay(file.0.clone(), true);
+    let (tx, rx) = mpsc::channel(32);
+    let (ev_tx, ev_rx) = mpsc::channel(8);
+    let silence = tokio::time::Duration::from_secs(60);
+    let ftest = tokio::spawn(reading_task(
+        tx.clone(),
+        false,
+        &TESSW,
+        endpoint.clone(),
+        None,
+        Some(ev_tx.clone()),
+        silence,
+    ));
+    let fref = tokio::spawn(reading_task(
+        tx,
+        true,
+        &REFERENCE,
+        endpoint,
+        None,
+        Some(ev_tx),
+        silence,
+    ));
+    let rounds = RoundOptions {
+        window: 9,
+        nrounds: NROUNDS,
+        ntests: 1,
+        millis: 5000,
+        profile: &TESSW,
+        silence,
+    };
+    let options = SessionOptions {
+        session,
+        persist: true,
+        ..Default::default()
+    };
+    statistics::calibration_task(
+        database::get_connection_pool(db.url()),
+        rx,
+        ev_rx,
+        Info::from_reading(&REFERENCE, &json("stars3", 0.0)),
+        Info::from_reading(&TESSW, &json("stars1", 0.0)),
+        rounds,
+        options,
+    )
+    .await
+    .unwrap();
+    ftest.await.unwrap().unwrap();
+    fref.await.unwrap().unwrap();
+}
+
+// Replays of one capture file have the same sample timestamps, told apart by session
+#[tokio::test]
+async fn calibrations_replayed_from_one_file_are_all_stored() {
+    let db = TempDb::new("calibration-replayed");
+    let mut lines = Vec::new();
+    for sec in 1..=60 {
+        for (role, name) in [("ref", "stars3"), ("test", "stars1")] {
+            let line = format!(
+                "{{\"udp\":{},\"rev\":1,\"name\":\"{}\",\"freq\":10.0,\"ZP\":{}}}",
+                sec, name, REF_ZP
+            );
+            lines.push((role, 1000 * sec, line));
+        }
+    }
+    let records = lines
+        .iter()
+        .map(|(role, millis, line)| (*role, *millis, line.as_str()))
+        .collect::<Vec<_>>();
+    let file = CaptureFile::new("calibration-replayed", &records).await;
+    let sessions = [
+        session_start(),
+        session_start() + chrono::Duration::hours(1),
+    ];
+    for session in sessions {
+        replayed_calibration(&db, &file, session).await;
+    }
+    let mut conn = database::get_connection_pool(db.url()).get().unwrap();
+    let summaries = summary_t::table
+        .count()
+        .get_result::<i64>(&mut conn)
+        .unwrap();
+    assert_eq!(summaries, 4);
+    let sessions = samples_t::table
+        .select(samples_t::session)
+        .distinct()
+        .load::<String>(&mut conn)
+        .unwrap();
+    assert_eq!(sessions.len(), 2);
+}
@@ -0,0 +1,66 @@
+// Helpers shared by the integration tests, not all of them used by every test
+#![allow(dead_code)]
+
+use chrono::prelude::*;
+use diesel::prelude::*;
+use diesel_migrations::MigrationHarness;
+use std::path::PathBuf;
+use zptess::database::{DbConnection, MIGRATIONS};
+use zptess::photometer::transport::{capture, Endpoint, RawSample, Transport};
+
+// An empty database with the current schema, removed when dropped
+pub struct TempDb(PathBuf);
+
+impl TempDb {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("zptess-{}-{}.db", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut conn = DbConnection::establish(path.to_str().unwrap()).unwrap();
+        conn.run_pending_migrations(MIGRATIONS).unwrap();
+        Self(path)
+    }
+
+    pub fn url(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// A capture file with the given records, removed when dropped.
+// Replays of the same file share their reader while any of them is open.
+pub struct CaptureFile(pub PathBuf);
+
+impl CaptureFile {
+    pub async fn new(name: &str, records: &[(&str, i64, &str)]) -> Self {
+        let path = std::env::temp_dir().join(format!(
+            "zptess-replay-{}-{}.jsonl",
+            name,
+            std::process::id()
+        ));
+        let (capture, fcapture) = capture::start(path.clone()).await.unwrap();
+        let t0 = Utc.with_ymd_and_hms(2026, 10, 18, 20, 0, 0).unwrap();
+        for (role, millis, line) in records {
+            let tstamp = t0 + chrono::Duration::milliseconds(*millis);
+            capture.record(role, "test", &RawSample(tstamp, line.to_string()));
+        }
+        drop(capture);
+        fcapture.await.unwrap().unwrap();
+        Self(path)
+    }
+
+    pub async fn open(&self, role: &str, fast: bool) -> Transport {
+        let endpoint = Endpoint::Replay(self.0.clone(), fast);
+        Transport::open(&endpoint, role).await.unwrap()
+    }
+}
+
+impl Drop for CaptureFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
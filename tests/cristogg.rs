@@ -0,0 +1,117 @@
+// Regression tests for the reference photometer payload decoder
+
+use bytes::BytesMut;
+use chrono::prelude::*;
+use tokio_util::codec::Decoder as _;
+use zptess::photometer::payload::cristogg::Decoder;
+use zptess::photometer::payload::{DecodeError, Payload};
+use zptess::photometer::transport::serial::LineCodec;
+
+const CORPUS: &str = include_str!("data/cristogg.txt");
+const MALFORMED: &str = include_str!("data/cristogg_malformed.txt");
+
+fn lines(corpus: &str) -> impl Iterator<Item = &str> {
+    corpus
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+}
+
+fn close(a: f32, b: f32) -> bool {
+    (a - b).abs() < 1e-3
+}
+
+#[test]
+fn corpus_lines_decode_to_their_values() {
+    let decoder = Decoder::new();
+    for record in lines(CORPUS) {
+        let fields: Vec<&str> = record.split('\t').collect();
+        let expected: Vec<f32> = fields[1..].iter().map(|f| f.parse().unwrap()).collect();
+        let cristogg = decoder
+            .parse(fields[0])
+            .unwrap_or_else(|e| panic!("{}: {}", fields[0], e));
+        assert!(close(cristogg.freq, expected[0]), "{} freq", fields[0]);
+        assert!(close(cristogg.tbox, expected[1]), "{} tbox", fields[0]);
+        assert!(close(cristogg.tsky, expected[2]), "{} tsky", fields[0]);
+        assert!(close(cristogg.zp, expected[3]), "{} zp", fields[0]);
+    }
+}
+
+#[test]
+fn hertz_and_millihertz_differ() {
+    let decoder = Decoder::new();
+    let hz = decoder
+        .parse("<fH 04606><tA +2987><tO +2481><mZ -0000>")
+        .unwrap();
+    let mhz = decoder
+        .parse("<fm 04606><tA +2987><tO +2481><mZ -0000>")
+        .unwrap();
+    assert!(close(hz.freq, 4606.0));
+    assert!(close(mhz.freq, 4.606));
+}
+
+// The zero point used to be taken as sent, in hundredths of magnitude
+#[test]
+fn zero_point_is_in_magnitudes() {
+    let decoder = Decoder::new();
+    let cristogg = decoder
+        .parse("<fH 00150><tA +2210><tO +1893><mZ +2050>")
+        .unwrap();
+    assert!(close(cristogg.zp, 20.50));
+    assert!(!close(cristogg.zp, 2050.0), "zero point in hundredths");
+}
+
+#[test]
+fn malformed_lines_are_decode_errors() {
+    for line in lines(MALFORMED) {
+        let mut decoder = Decoder::new();
+        match decoder.decode(Utc::now(), line) {
+            Err(DecodeError::Malformed(_)) => {}
+            other => panic!("{:?} decoded as {:?}", line, other),
+        }
+    }
+}
+
+// Line noise on the serial port is rejected by the payload decoder, not by the port reader
+#[test]
+fn garbled_bytes_make_malformed_lines() {
+    let mut src = BytesMut::from(&b"<fH 04\xff06><tA +2987><tO +2481><mZ -0000>\r\n"[..]);
+    let line = LineCodec.decode(&mut src).unwrap().expect("a whole line");
+    assert!(src.is_empty());
+    let mut decoder = Decoder::new();
+    match decoder.decode(Utc::now(), line.trim()) {
+        Err(DecodeError::Malformed(_)) => {}
+        other => panic!("{:?} decoded as {:?}", line, other),
+    }
+}
+
+#[test]
+fn negative_frequency_is_out_of_range() {
+    let mut decoder = Decoder::new();
+    let line = "<fH-00150><tA +2210><tO +1893><mZ +2050>";
+    assert!(matches!(
+        decoder.decode(Utc::now(), line),
+        Err(DecodeError::OutOfRange(_))
+    ));
+}
+
+#[test]
+fn first_and_repeated_readings_are_held_back() {
+    let mut decoder = Decoder::new();
+    let first = "<fm 04606><tA +2987><tO +2481><mZ +2050>";
+    let second = "<fm 04610><tA +2987><tO +2481><mZ +2050>";
+    let t0 = Utc::now();
+    assert_eq!(
+        decoder.decode(t0, first).unwrap_err(),
+        DecodeError::Bootstrap
+    );
+    assert_eq!(
+        decoder.decode(t0, first).unwrap_err(),
+        DecodeError::Duplicate
+    );
+    let (tstamp, payload) = decoder.decode(t0, second).unwrap();
+    assert_eq!(tstamp, t0);
+    let Payload::Cristogg(cristogg) = payload else {
+        panic!("not a Cristogg payload");
+    };
+    assert!(close(cristogg.freq, 4.606));
+}
@@ -0,0 +1,202 @@
+// Lines yielding no samples are counted by photometer and outcome, from the reading task
+// to the round log, whose final counts are those stored in decoding_t
+
+mod common;
+
+use common::{CaptureFile, TempDb};
+use diesel::prelude::*;
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+use zptess::database::{self, schema::decoding_t};
+use zptess::photometer::discovery::Info;
+use zptess::photometer::payload::{DecodeStats, Json};
+use zptess::photometer::profile::{REFERENCE, TESSW};
+use zptess::photometer::transport::Endpoint;
+use zptess::photometer::{reading_task, Event, Status};
+use zptess::statistics::{self, RoundOptions, SessionOptions};
+
+fn reading(name: &str, udp: u32, freq: f32) -> String {
+    format!(
+        "{{\"udp\":{},\"rev\":1,\"name\":\"{}\",\"freq\":{},\"ZP\":20.44}}",
+        udp, name, freq
+    )
+}
+
+// Test photometer lines: stars1 sends one of each kind of rejected line
+// and stars2 sends a duplicate, besides lines naming no photometer
+fn rejected_lines() -> Vec<String> {
+    let truncated = reading("stars1", 3, 10.0);
+    vec![
+        reading("stars1", 1, 10.0), // bootstrap
+        reading("stars1", 1, 10.0), // duplicate
+        "{\"udp\":2,\"name\":\"stars1\",\"freq\":\"ten\"}".to_string(), // malformed
+        reading("stars1", 2, -10.0), // out of range
+        truncated[..truncated.len() - 10].to_string(), // malformed
+        reading("stars2", 1, 10.0), // bootstrap
+        reading("stars2", 1, 10.0), // duplicate
+        "garbage".to_string(),      // unknown format
+        truncated[..10].to_string(), // malformed, before the name
+    ]
+}
+
+// Rejected line counts: malformed, duplicate, bootstrap, out of range, unknown format
+fn rejected(stats: &DecodeStats) -> [u32; 5] {
+    [
+        stats.malformed,
+        stats.duplicate,
+        stats.bootstrap,
+        stats.out_of_range,
+        stats.unknown_format,
+    ]
+}
+
+#[tokio::test]
+async fn rejected_lines_are_counted_per_photometer() {
+    let lines = rejected_lines();
+    let records = lines
+        .iter()
+        .enumerate()
+        .map(|(i, line)| ("test", 100 * i as i64, line.as_str()))
+        .collect::<Vec<_>>();
+    let file = CaptureFile::new("rejected", &records).await;
+    let (tx, mut rx) = mpsc::channel(32);
+    let (ev_tx, mut ev_rx) = mpsc::channel::<Event>(8);
+    tokio::spawn(reading_task(
+        tx,
+        false,
+        &TESSW,
+        Endpoint::Replay(file.0.clone(), true),
+        None,
+        Some(ev_tx),
+        Duration::from_secs(60),
+    ));
+    tokio::spawn(async move { while rx.recv().await.is_some() {} });
+    // Every report supersedes the previous one of the same photometer
+    let mut reported = HashMap::new();
+    while let Some(event) = ev_rx.recv().await {
+        match event.status {
+            Status::Rejected(key, stats) => {
+                reported.insert(key, stats);
+            }
+            Status::Ended(_) => break,
+            _ => {}
+        }
+    }
+    assert_eq!(reported.len(), 3);
+    assert_eq!(rejected(&reported["stars1"]), [2, 1, 1, 1, 0]);
+    assert_eq!(rejected(&reported["stars2"]), [0, 1, 1, 0, 0]);
+    assert_eq!(rejected(&reported[""]), [1, 0, 0, 0, 1]);
+}
+
+// A calibration of two test photometers fed from a replay with rejected lines at its start
+#[tokio::test]
+async fn round_log_counts_are_kept_per_photometer() {
+    let db = TempDb::new("decoding-rounds");
+    let mut records = Vec::new();
+    for (i, line) in rejected_lines().into_iter().enumerate() {
+        records.push(("test", i as i64, line));
+    }
+    for sec in 1..=60 {
+        let millis = 1000 * sec as i64;
+        records.push(("ref", millis, reading("stars3", sec, 10.0)));
+        records.push(("test", millis + 100, reading("stars1", 10 + sec, 10.0)));
+        records.push(("test", millis + 200, reading("stars2", 10 + sec, 20.0)));
+    }
+    let records = records
+        .iter()
+        .map(|(role, millis, line)| (*role, *millis, line.as_str()))
+        .collect::<Vec<_>>();
+    let file = CaptureFile::new("decoding-rounds", &records).await;
+    let endpoint = Endpoint::Replay(file.0.clone(), true);
+    let (tx, rx) = mpsc::channel(32);
+    let (ev_tx, ev_rx) = mpsc::channel::<Event>(8);
+    let silence = Duration::from_secs(60);
+    let ftest = tokio::spawn(reading_task(
+        tx.clone(),
+        false,
+        &TESSW,
+        endpoint.clone(),
+        None,
+        Some(ev_tx.clone()),
+        silence,
+    ));
+    let fref = tokio::spawn(reading_task(
+        tx,
+        true,
+        &REFERENCE,
+        endpoint,
+        None,
+        Some(ev_tx),
+        silence,
+    ));
+    let ref_info = Info::from_reading(
+        &REFERENCE,
+        &serde_json::from_str::<Json>(&reading("stars3", 0, 10.0)).unwrap(),
+    );
+    let test_info = Info::from_reading(
+        &TESSW,
+        &serde_json::from_str::<Json>(&reading("stars1", 0, 10.0)).unwrap(),
+    );
+    let rounds = RoundOptions {
+        window: 9,
+        nrounds: 3,
+        ntests: 2,
+        millis: 5000,
+        profile: &TESSW,
+        silence,
+    };
+    let options = SessionOptions {
+        persist: true,
+        ..Default::default()
+    };
+    statistics::calibration_task(
+        database::get_connection_pool(db.url()),
+        rx,
+        ev_rx,
+        ref_info,
+        test_info,
+        rounds,
+        options,
+    )
+    .await
+    .unwrap();
+    ftest.await.unwrap().unwrap();
+    fref.await.unwrap().unwrap();
+
+    let mut conn = database::get_connection_pool(db.url()).get().unwrap();
+    let rows = decoding_t::table
+        .order(decoding_t::name)
+        .select((
+            decoding_t::name,
+            decoding_t::malformed,
+            decoding_t::duplicate,
+            decoding_t::bootstrap,
+            decoding_t::out_of_range,
+            decoding_t::unknown_format,
+        ))
+        .load::<(
+            String,
+            Option<i32>,
+            Option<i32>,
+            Option<i32>,
+            Option<i32>,
+            Option<i32>,
+        )>(&mut conn)
+        .unwrap();
+    let counts = rows
+        .into_iter()
+        .map(|(name, m, d, b, o, u)| (name, [m, d, b, o, u].map(|n| n.unwrap())))
+        .collect::<Vec<_>>();
+    // Every photometer first reading only bootstraps its duplicate filter,
+    // and lines naming no photometer are kept apart
+    assert_eq!(
+        counts,
+        [
+            ("".to_string(), [1, 0, 0, 0, 1]),
+            ("stars1".to_string(), [2, 1, 1, 1, 0]),
+            ("stars2".to_string(), [0, 1, 1, 0, 0]),
+            ("stars3".to_string(), [0, 0, 1, 0, 0]),
+        ]
+    );
+}
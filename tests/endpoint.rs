@@ -0,0 +1,87 @@
+// Endpoint specs given in the command line or the device sections of config_t
+
+use zptess::photometer::transport::{serial, Endpoint};
+
+fn parse(spec: &str) -> Endpoint {
+    spec.parse::<Endpoint>()
+        .unwrap_or_else(|e| panic!("{}: {}", spec, e))
+}
+
+#[test]
+fn endpoints_display_as_parsed() {
+    for spec in [
+        "serial:/dev/ttyUSB0:9600",
+        "serial:/dev/ttyACM0:115200",
+        "udp:0.0.0.0:2255",
+        "udp:192.168.1.10:2256",
+        "tcp:host:23",
+        "tcp:192.168.4.1:23",
+        "mqtt:broker:1883/STARS4ALL/+/reading",
+        "mqtt:192.168.1.2:8883/stars1",
+    ] {
+        assert_eq!(parse(spec).to_string(), spec);
+    }
+}
+
+#[test]
+fn serial_endpoints() {
+    let Endpoint::Serial(port) = parse("serial:/dev/ttyUSB0:9600") else {
+        panic!("not a serial endpoint");
+    };
+    assert_eq!(port.device, "/dev/ttyUSB0");
+    assert_eq!(port.baud, 9600);
+    // Device names may contain colons, only a trailing number is a baud rate
+    let Endpoint::Serial(port) = parse("serial:/dev/serial/by-path/pci-0:1.2") else {
+        panic!("not a serial endpoint");
+    };
+    assert_eq!(port.device, "/dev/serial/by-path/pci-0:1.2");
+    assert_eq!(port.baud, serial::DEFAULT_BAUD);
+}
+
+#[test]
+fn mqtt_endpoints() {
+    let Endpoint::Mqtt(host, port, topic) = parse("mqtt:broker:1884/STARS4ALL/stars1/reading")
+    else {
+        panic!("not an MQTT endpoint");
+    };
+    assert_eq!(host, "broker");
+    assert_eq!(port, 1884);
+    assert_eq!(topic, "STARS4ALL/stars1/reading");
+    assert_eq!(
+        parse("mqtt:broker/stars1").to_string(),
+        "mqtt:broker:1883/stars1"
+    );
+}
+
+#[test]
+fn defaults_are_filled_in() {
+    assert_eq!(
+        parse("serial:/dev/ttyUSB1").to_string(),
+        "serial:/dev/ttyUSB1:9600"
+    );
+    assert_eq!(parse("udp:2255"), Endpoint::default_udp());
+    assert_eq!(parse("serial:/dev/ttyUSB0"), Endpoint::default_serial());
+}
+
+#[test]
+fn malformed_endpoints_are_rejected() {
+    for spec in [
+        "",
+        "/dev/ttyUSB0",
+        "ftp:host:21",
+        "serial:",
+        "udp:",
+        "udp:0.0.0.0:99999",
+        "udp:0.0.0.0:port",
+        "tcp:host",
+        "tcp::23",
+        "tcp:host:",
+        "tcp:host:port",
+        "mqtt:broker",
+        "mqtt:broker:1883/",
+        "mqtt:/stars1",
+        "mqtt:broker:port/stars1",
+    ] {
+        assert!(spec.parse::<Endpoint>().is_err(), "{:?} accepted", spec);
+    }
+}
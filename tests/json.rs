@@ -0,0 +1,111 @@
+// Regression tests for the decoding and udp sequence counter of JSON readings
+
+use chrono::prelude::*;
+use zptess::photometer::payload::json::Decoder;
+use zptess::photometer::payload::{DecodeError, Json, Payload, Sequence};
+
+fn line(udp: u32) -> String {
+    format!(
+        "{{\"udp\":{},\"rev\":2,\"name\":\"stars1\",\"freq\":4.6,\"mag\":20.1,\"tamb\":20.0,\"tsky\":15.0,\"wdBm\":-60,\"ain\":500,\"ZP\":20.5}}",
+        udp
+    )
+}
+
+// Outcome and sequence report of each udp counter, in order
+fn feed(counters: &[u32]) -> Vec<(Result<u32, DecodeError>, Option<Sequence>)> {
+    let mut decoder = Decoder::new();
+    counters
+        .iter()
+        .map(|udp| {
+            let outcome = decoder.decode(Utc::now(), &line(*udp)).map(|_| *udp);
+            (outcome, decoder.take_sequence())
+        })
+        .collect()
+}
+
+#[test]
+fn consecutive_readings_report_nothing() {
+    let outcomes = feed(&[100, 101, 102]);
+    assert_eq!(outcomes[0], (Err(DecodeError::Bootstrap), None));
+    assert_eq!(outcomes[1], (Ok(101), None));
+    assert_eq!(outcomes[2], (Ok(102), None));
+}
+
+#[test]
+fn gaps_report_lost_readings() {
+    let outcomes = feed(&[100, 101, 105]);
+    assert_eq!(outcomes[2], (Ok(105), Some(Sequence::Lost(3))));
+}
+
+#[test]
+fn duplicates_are_rejected() {
+    let outcomes = feed(&[100, 101, 101, 102]);
+    assert_eq!(outcomes[2], (Err(DecodeError::Duplicate), None));
+    assert_eq!(outcomes[3], (Ok(102), None));
+}
+
+#[test]
+fn late_readings_are_out_of_order() {
+    let outcomes = feed(&[100, 102, 101, 103]);
+    assert_eq!(outcomes[1], (Ok(102), Some(Sequence::Lost(1))));
+    assert_eq!(outcomes[2], (Err(DecodeError::OutOfOrder), None));
+    assert_eq!(outcomes[3], (Ok(103), None));
+}
+
+#[test]
+fn counter_reset_is_a_reboot() {
+    let outcomes = feed(&[100, 101, 1, 2]);
+    assert_eq!(outcomes[2], (Ok(1), Some(Sequence::Reboot)));
+    assert_eq!(outcomes[3], (Ok(2), None));
+    let outcomes = feed(&[5000, 5001, 4000]);
+    assert_eq!(outcomes[2], (Ok(4000), Some(Sequence::Reboot)));
+}
+
+#[test]
+fn counters_near_the_top_do_not_overflow() {
+    let outcomes = feed(&[u32::MAX - 3, u32::MAX - 1, u32::MAX, 0]);
+    assert_eq!(outcomes[1], (Ok(u32::MAX - 1), Some(Sequence::Lost(1))));
+    assert_eq!(outcomes[2], (Ok(u32::MAX), None));
+    assert_eq!(outcomes[3], (Ok(0), Some(Sequence::Reboot)));
+}
+
+// The first reading, handed out once the next one arrives
+fn second(first: &str, line: &str) -> Result<Json, DecodeError> {
+    let mut decoder = Decoder::new();
+    assert_eq!(
+        decoder.decode(Utc::now(), first).err(),
+        Some(DecodeError::Bootstrap)
+    );
+    decoder
+        .decode(Utc::now(), line)
+        .map(|(_, payload)| match payload {
+            Payload::Json(json) => json,
+            Payload::Cristogg(_) => unreachable!("JSON decoder"),
+        })
+}
+
+#[test]
+fn missing_optional_fields_are_decoded() {
+    let first = "{\"name\":\"stars1\",\"freq\":4.6}";
+    let json = second(first, "{\"name\":\"stars1\",\"freq\":4.7}").unwrap();
+    assert_eq!((json.name.as_str(), json.freq), ("stars1", 4.6));
+    assert_eq!(
+        (json.udp, json.rev, json.mag, json.ZP),
+        (None, None, None, None)
+    );
+}
+
+#[test]
+fn unknown_extra_fields_are_kept() {
+    let first = "{\"udp\":1,\"rev\":2,\"name\":\"stars1\",\"freq\":4.6,\"hum\":55.0}";
+    let json = second(first, &line(2)).unwrap();
+    assert_eq!(json.freq, 4.6);
+    assert_eq!(json.extra.get("hum"), Some(&serde_json::json!(55.0)));
+}
+
+#[test]
+fn unknown_firmware_revision_is_decoded() {
+    let first = "{\"udp\":1,\"rev\":99,\"name\":\"stars1\",\"freq\":4.6,\"ZP\":20.5}";
+    let json = second(first, &line(2)).unwrap();
+    assert_eq!((json.rev, json.ZP), (Some(99), Some(20.5)));
+}
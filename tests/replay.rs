@@ -0,0 +1,131 @@
+// Captured traffic is fed back in order, at its pace or as fast as possible, until the end of the file
+
+mod common;
+
+use common::CaptureFile;
+use std::io::ErrorKind;
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+use zptess::photometer::profile::TESSW;
+use zptess::photometer::transport::{Endpoint, RawSample, Transport};
+use zptess::photometer::{reading_task, Event, Status};
+
+async fn line(transport: &mut Transport) -> String {
+    let RawSample(_, line) = transport.reading().await.unwrap();
+    line
+}
+
+#[tokio::test]
+async fn records_keep_their_timestamps_and_order() {
+    let file = CaptureFile::new("order", &[("test", 0, "a"), ("test", 1000, "b")]).await;
+    let mut transport = file.open("test", true).await;
+    let RawSample(t0, a) = transport.reading().await.unwrap();
+    let RawSample(t1, b) = transport.reading().await.unwrap();
+    assert_eq!((a.as_str(), b.as_str()), ("a", "b"));
+    assert_eq!((t1 - t0).num_milliseconds(), 1000);
+}
+
+#[tokio::test]
+async fn each_role_gets_its_own_records() {
+    let records = [
+        ("ref", 0, "r1"),
+        ("test", 100, "t1"),
+        ("ref", 200, "r2"),
+        ("test", 300, "t2"),
+    ];
+    let file = CaptureFile::new("roles", &records).await;
+    let mut refe = file.open("ref", true).await;
+    let mut test = file.open("test", true).await;
+    assert_eq!(line(&mut test).await, "t1");
+    assert_eq!(line(&mut test).await, "t2");
+    assert_eq!(line(&mut refe).await, "r1");
+    assert_eq!(line(&mut refe).await, "r2");
+}
+
+#[tokio::test]
+async fn original_pace_is_kept() {
+    let file = CaptureFile::new("pace", &[("test", 0, "a"), ("test", 400, "b")]).await;
+    let mut transport = file.open("test", false).await;
+    line(&mut transport).await;
+    let start = Instant::now();
+    line(&mut transport).await;
+    assert!(start.elapsed() >= Duration::from_millis(350));
+}
+
+#[tokio::test]
+async fn fast_replays_do_not_wait() {
+    let file = CaptureFile::new("fast", &[("test", 0, "a"), ("test", 60000, "b")]).await;
+    let mut transport = file.open("test", true).await;
+    let start = Instant::now();
+    line(&mut transport).await;
+    line(&mut transport).await;
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+// The reading task takes the end of the file as the end of the readings
+#[tokio::test]
+async fn end_of_file_is_reported_every_time() {
+    let file = CaptureFile::new("eof", &[("ref", 0, "r1"), ("test", 100, "t1")]).await;
+    let mut transport = file.open("test", true).await;
+    assert_eq!(line(&mut transport).await, "t1");
+    for _ in 0..2 {
+        let e = transport.reading().await.err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+    }
+}
+
+// Once every role is gone, the file is replayed again from its start
+#[tokio::test]
+async fn replayed_files_can_be_reopened() {
+    let file = CaptureFile::new("reopen", &[("ref", 0, "r1"), ("test", 100, "t1")]).await;
+    for _ in 0..2 {
+        let mut refe = file.open("ref", true).await;
+        let mut test = file.open("test", true).await;
+        assert_eq!(line(&mut test).await, "t1");
+        assert_eq!(line(&mut refe).await, "r1");
+        assert!(test.reading().await.is_err());
+    }
+}
+
+// Readings of the test photometer in the given replay, until the task tells they ended
+async fn ended(endpoint: Endpoint) -> (anyhow::Result<()>, Option<String>) {
+    let (tx, mut rx) = mpsc::channel(32);
+    let (ev_tx, mut ev_rx) = mpsc::channel::<Event>(8);
+    let task = tokio::spawn(reading_task(
+        tx,
+        false,
+        &TESSW,
+        endpoint,
+        None,
+        Some(ev_tx),
+        Duration::from_secs(60),
+    ));
+    tokio::spawn(async move { while rx.recv().await.is_some() {} });
+    let mut reason = None;
+    while let Some(event) = ev_rx.recv().await {
+        if let Status::Ended(r) = event.status {
+            reason = Some(r);
+            break;
+        }
+    }
+    let reason = reason.expect("no Ended event");
+    (task.await.unwrap(), reason)
+}
+
+#[tokio::test]
+async fn end_of_file_ends_the_readings() {
+    let line = r#"{"udp":1,"rev":1,"name":"stars1","freq":10.0,"mag":12.0,"tamb":10.0,"tsky":-20.0,"wdBm":-50,"ain":0,"ZP":20.5}"#;
+    let file = CaptureFile::new("ended", &[("test", 0, line)]).await;
+    let (result, reason) = ended(Endpoint::Replay(file.0.clone(), true)).await;
+    assert!(result.is_ok());
+    assert_eq!(reason, None);
+}
+
+#[tokio::test]
+async fn open_failures_end_the_readings() {
+    let path = std::env::temp_dir().join("zptess-replay-missing.jsonl");
+    let (result, reason) = ended(Endpoint::Replay(path.clone(), true)).await;
+    assert!(result.is_err());
+    let reason = reason.expect("no error given");
+    assert!(reason.contains(&path.display().to_string()), "{}", reason);
+}
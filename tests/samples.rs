@@ -0,0 +1,56 @@
+// Samples of photometers calibrated together are all stored
+
+mod common;
+
+use chrono::prelude::*;
+use common::TempDb;
+use diesel::prelude::*;
+use zptess::database::{self, schema::samples_t};
+use zptess::photometer::payload::{Json, Payload};
+use zptess::statistics::dao::{self, Dao};
+
+fn payload(name: &str, freq: f32, channel: Option<u8>) -> Payload {
+    let line = format!("{{\"udp\":1,\"name\":\"{}\",\"freq\":{}}}", name, freq);
+    let mut json = serde_json::from_str::<Json>(&line).unwrap();
+    json.channel = channel;
+    Payload::Json(json)
+}
+
+async fn store(db: &TempDb, photometers: &[(&str, Option<u8>)]) -> i64 {
+    let tstamp = Utc.with_ymd_and_hms(2026, 10, 18, 20, 0, 0).unwrap();
+    let samples = photometers
+        .iter()
+        .map(|(name, channel)| {
+            let payload = payload(name, 4.5, *channel);
+            dao::sample_row(
+                "2026-10-18T20:00:00",
+                "test",
+                name,
+                *channel,
+                &tstamp,
+                &payload,
+            )
+        })
+        .collect::<Vec<_>>();
+    let pool = database::get_connection_pool(db.url());
+    Dao::new(pool.clone()).write_samples(samples).await.unwrap();
+    samples_t::table
+        .count()
+        .get_result::<i64>(&mut pool.get().unwrap())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn two_test_photometers_at_one_timestamp() {
+    let db = TempDb::new("two-tests");
+    let stored = store(&db, &[("stars1", None), ("stars2", None)]).await;
+    assert_eq!(stored, 2);
+}
+
+#[tokio::test]
+async fn four_channels_at_one_timestamp() {
+    let db = TempDb::new("four-channels");
+    let channels = [1, 2, 3, 4].map(|channel| ("stars4c", Some(channel)));
+    let stored = store(&db, &channels).await;
+    assert_eq!(stored, 4);
+}
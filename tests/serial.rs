@@ -0,0 +1,81 @@
+// Commands sent to a serial photometer while its readings keep streaming,
+// driven through an in-memory stream instead of a serial port
+
+use regex::Regex;
+use tokio::io::{duplex, AsyncBufReadExt, AsyncWriteExt, BufReader, DuplexStream};
+use zptess::photometer::transport::serial::Transport;
+use zptess::photometer::transport::RawSample;
+
+const READING: &str = "<fH 04606><tA +2987><tO +2481><mZ -0000>";
+
+fn transport() -> (Transport<DuplexStream>, DuplexStream) {
+    let (port, photometer) = duplex(1024);
+    (Transport::from_stream(port), photometer)
+}
+
+#[tokio::test]
+async fn responses_are_matched_while_readings_flow() {
+    let (mut transport, photometer) = transport();
+    let commander = transport.commander();
+    // The photometer answers the command between two readings
+    let (input, mut output) = tokio::io::split(photometer);
+    let device = tokio::spawn(async move {
+        let mut commands = BufReader::new(input).lines();
+        output
+            .write_all(format!("{READING}\r\n").as_bytes())
+            .await
+            .unwrap();
+        let command = commands.next_line().await.unwrap().unwrap();
+        assert_eq!(command, "?");
+        output
+            .write_all(format!("{READING}\r\n").as_bytes())
+            .await
+            .unwrap();
+        output.write_all(b"<ZP 20.44>\r\n").await.unwrap();
+        output
+            .write_all(format!("{READING}\r\n").as_bytes())
+            .await
+            .unwrap();
+        output
+    });
+    let response = Regex::new(r"^<ZP").unwrap();
+    let reply = tokio::spawn(async move { commander.command("?", &response).await });
+    let mut readings = Vec::new();
+    for _ in 0..3 {
+        let RawSample(_, line) = transport.reading().await.unwrap();
+        readings.push(line);
+    }
+    assert_eq!(readings, [READING; 3]);
+    assert_eq!(reply.await.unwrap().unwrap(), "<ZP 20.44>");
+    drop(device.await.unwrap());
+}
+
+#[tokio::test]
+async fn commands_time_out_when_nothing_answers() {
+    let (mut transport, photometer) = transport();
+    let commander = transport.commander();
+    let response = Regex::new(r"^<ZP").unwrap();
+    let reply = tokio::spawn(async move { commander.command("?", &response).await });
+    // Readings only, never the response
+    let (_input, mut output) = tokio::io::split(photometer);
+    let device = tokio::spawn(async move {
+        loop {
+            let line = format!("{READING}\r\n");
+            if output.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+            tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+        }
+    });
+    let result = loop {
+        let RawSample(_, line) = transport.reading().await.unwrap();
+        assert_eq!(line, READING);
+        if reply.is_finished() {
+            break reply.await.unwrap();
+        }
+    };
+    let e = result.unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::TimedOut);
+    assert!(e.to_string().contains("No response to command"), "{}", e);
+    device.abort();
+}
@@ -0,0 +1,140 @@
+// Simulation parameters and the readings they generate, as seen by the payload decoders
+
+use zptess::photometer::payload::{cristogg, json, DecodeError, Sequence};
+use zptess::photometer::transport::simulator::{Format, Params, Transport};
+use zptess::photometer::transport::RawSample;
+
+const NREADINGS: usize = 1000;
+
+fn params(spec: &str) -> Params {
+    spec.parse::<Params>()
+        .unwrap_or_else(|e| panic!("{}: {}", spec, e))
+}
+
+// Fast simulators share a clock, so a test only drives one of them at a time
+async fn readings(spec: &str, n: usize) -> Vec<RawSample> {
+    let mut transport = Transport::new(&params(spec));
+    let mut readings = Vec::with_capacity(n);
+    for _ in 0..n {
+        readings.push(transport.reading().await.unwrap());
+    }
+    readings
+}
+
+fn udp(line: &str) -> u64 {
+    let value = serde_json::from_str::<serde_json::Value>(line).unwrap();
+    value["udp"].as_u64().unwrap()
+}
+
+#[test]
+fn parameters_are_parsed() {
+    let p = params("cristogg,name=stars9,freq=12.5,noise=0,drift=0.5,gap=0.1,dup=0.2,period=3,zp=20.44,seed=7,fast=true");
+    assert_eq!(p.format, Format::Cristogg);
+    assert_eq!(p.name, "stars9");
+    assert_eq!((p.freq, p.noise, p.drift), (12.5, 0.0, 0.5));
+    assert_eq!((p.gap, p.dup, p.period, p.zp), (0.1, 0.2, 3.0, 20.44));
+    assert_eq!((p.seed, p.fast), (7, true));
+    assert_eq!(params(&p.to_string()), p);
+}
+
+#[test]
+fn defaults_depend_on_the_format() {
+    assert_eq!(params("json").period, 1.0);
+    assert_eq!(params("cristogg").period, 2.0);
+    let p = params("json,freq=8");
+    assert_eq!((p.freq, p.name.as_str(), p.zp), (8.0, "stars0", 20.5));
+}
+
+#[test]
+fn malformed_parameters_are_rejected() {
+    for spec in [
+        "",
+        "xml",
+        "json,freq",
+        "json,freq=abc",
+        "json,fast=yes",
+        "json,bogus=1",
+        "json,period=0",
+        "cristogg,period=-2",
+        "json,period=nan",
+        "json,period=inf",
+        "json,gap=1",
+        "json,gap=-0.1",
+        "json,dup=1",
+        "json,dup=nan",
+    ] {
+        assert!(spec.parse::<Params>().is_err(), "{:?} accepted", spec);
+    }
+}
+
+#[tokio::test]
+async fn fast_timestamps_advance_by_the_period() {
+    let readings = readings("json,period=2.5,fast=true", 4).await;
+    for pair in readings.windows(2) {
+        assert_eq!((pair[1].0 - pair[0].0).num_milliseconds(), 2500);
+    }
+}
+
+#[tokio::test]
+async fn json_counters_are_consecutive_without_gaps() {
+    let readings = readings("json,fast=true", 20).await;
+    let counters: Vec<u64> = readings.iter().map(|r| udp(&r.1)).collect();
+    assert_eq!(counters, (1..=20).collect::<Vec<u64>>());
+}
+
+#[tokio::test]
+async fn json_gaps_are_lost_readings() {
+    let readings = readings("json,gap=0.2,seed=3,fast=true", NREADINGS).await;
+    let mut decoder = json::Decoder::new();
+    let mut lost = 0;
+    for RawSample(tstamp, line) in readings.iter() {
+        let _ = decoder.decode(*tstamp, line);
+        if let Some(Sequence::Lost(n)) = decoder.take_sequence() {
+            lost += n;
+        }
+    }
+    let sent = udp(&readings[NREADINGS - 1].1) as u32;
+    assert_eq!(lost + NREADINGS as u32, sent);
+    // One reading lost in four received, on average
+    assert!((150..350).contains(&lost), "{} lost", lost);
+}
+
+#[tokio::test]
+async fn json_repeats_are_duplicates() {
+    let readings = readings("json,dup=0.2,seed=5,fast=true", NREADINGS).await;
+    let repeats = readings.windows(2).filter(|w| w[0].1 == w[1].1).count();
+    assert!((120..280).contains(&repeats), "{} repeats", repeats);
+    let mut decoder = json::Decoder::new();
+    let duplicates = readings
+        .iter()
+        .filter(|RawSample(tstamp, line)| {
+            matches!(decoder.decode(*tstamp, line), Err(DecodeError::Duplicate))
+        })
+        .count();
+    assert_eq!(duplicates, repeats);
+}
+
+#[tokio::test]
+async fn cristogg_repeats_are_duplicates() {
+    let readings = readings("cristogg,dup=0.2,seed=5,fast=true", NREADINGS).await;
+    let repeats = readings.windows(2).filter(|w| w[0].1 == w[1].1).count();
+    assert!(repeats > 0);
+    let mut decoder = cristogg::Decoder::new();
+    let duplicates = readings
+        .iter()
+        .filter(|RawSample(tstamp, line)| {
+            matches!(decoder.decode(*tstamp, line), Err(DecodeError::Duplicate))
+        })
+        .count();
+    assert_eq!(duplicates, repeats);
+}
+
+#[tokio::test]
+async fn noiseless_readings_are_at_the_given_frequency() {
+    let readings = readings("cristogg,freq=12.345,noise=0,fast=true", 3).await;
+    let decoder = cristogg::Decoder::new();
+    for RawSample(_, line) in readings.iter() {
+        let cristogg = decoder.parse(line).unwrap();
+        assert!((cristogg.freq - 12.345).abs() < 1e-3, "{}", line);
+    }
+}
@@ -0,0 +1,63 @@
+// TESS4C readings are split per channel and their udp counter followed as for single channel ones
+
+use chrono::prelude::*;
+use zptess::photometer::payload::tess4c::Decoder;
+use zptess::photometer::payload::{DecodeError, Payload, Sequence};
+
+fn line(udp: u32) -> String {
+    format!(
+        "{{\"udp\":{},\"rev\":1,\"name\":\"stars4c\",\"freq1\":4.1,\"mag1\":20.1,\"ZP1\":20.5,\"freq2\":4.2,\"mag2\":20.2,\"ZP2\":20.6,\"freq3\":4.3,\"mag3\":20.3,\"ZP3\":20.7,\"freq4\":4.4,\"mag4\":20.4,\"ZP4\":20.8,\"tamb\":20.0,\"tsky\":15.0,\"wdBm\":-60,\"ain\":500}}",
+        udp
+    )
+}
+
+fn channels(outcome: Result<Vec<(DateTime<Utc>, Payload)>, DecodeError>) -> Vec<Option<u8>> {
+    outcome
+        .unwrap()
+        .into_iter()
+        .map(|(_, payload)| match payload {
+            Payload::Json(json) => json.channel,
+            Payload::Cristogg(_) => None,
+        })
+        .collect()
+}
+
+#[test]
+fn one_payload_per_channel() {
+    let mut decoder = Decoder::new();
+    let outcome = decoder.decode(Utc::now(), &line(100));
+    assert_eq!(channels(outcome), [Some(1), Some(2), Some(3), Some(4)]);
+}
+
+#[test]
+fn only_name_and_frequencies_are_needed() {
+    let mut decoder = Decoder::new();
+    let line = "{\"name\":\"stars4c\",\"freq1\":4.1,\"freq3\":4.3}";
+    assert!(decoder.matches(line));
+    let outcome = decoder.decode(Utc::now(), line);
+    assert_eq!(channels(outcome), [Some(1), Some(3)]);
+}
+
+#[test]
+fn single_channel_readings_do_not_match() {
+    let decoder = Decoder::new();
+    assert!(!decoder.matches("{\"udp\":1,\"name\":\"stars1\",\"freq\":4.6}"));
+}
+
+#[test]
+fn udp_counter_is_followed() {
+    let mut decoder = Decoder::new();
+    assert!(decoder.decode(Utc::now(), &line(100)).is_ok());
+    assert_eq!(
+        decoder.decode(Utc::now(), &line(100)).err(),
+        Some(DecodeError::Duplicate)
+    );
+    assert!(decoder.decode(Utc::now(), &line(103)).is_ok());
+    assert_eq!(decoder.take_sequence(), Some(Sequence::Lost(2)));
+    assert_eq!(
+        decoder.decode(Utc::now(), &line(102)).err(),
+        Some(DecodeError::OutOfOrder)
+    );
+    assert!(decoder.decode(Utc::now(), &line(1)).is_ok());
+    assert_eq!(decoder.take_sequence(), Some(Sequence::Reboot));
+}
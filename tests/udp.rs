@@ -0,0 +1,154 @@
+// Selecting one photometer among those sending datagrams to the same UDP port
+
+use chrono::prelude::*;
+use std::net::{IpAddr, SocketAddr};
+use tokio::net::UdpSocket;
+use zptess::photometer::payload::tess4c::Decoder;
+use zptess::photometer::transport::udp::{photometer_name, Filter, Transport};
+use zptess::photometer::transport::{Endpoint, RawSample};
+
+const STARS1: &str = r#"{"udp":1,"rev":1,"name":"stars1","freq":10.0,"mag":12.0}"#;
+const STARS2: &str = r#"{"udp":1,"rev":1,"name":"stars2","freq":10.0,"mag":12.0}"#;
+
+fn filter(spec: &str) -> Filter {
+    match spec.parse::<Endpoint>() {
+        Ok(Endpoint::Udp(_, _, filter)) => filter,
+        other => panic!("{}: {:?}", spec, other),
+    }
+}
+
+fn src(ip: &str) -> SocketAddr {
+    SocketAddr::new(ip.parse().unwrap(), 2255)
+}
+
+#[test]
+fn filters_are_parsed() {
+    assert!(filter("udp:2255").is_empty());
+    let f = filter("udp:0.0.0.0:2255,from=192.168.1.10,name=stars1");
+    assert_eq!(f.from, Some("192.168.1.10".parse::<IpAddr>().unwrap()));
+    assert_eq!(f.name.as_deref(), Some("stars1"));
+    assert!(!f.shared);
+    assert_eq!(filter("udp:2255,name=stars1").from, None);
+}
+
+#[test]
+fn filters_display_as_parsed() {
+    for spec in [
+        "udp:0.0.0.0:2255,from=192.168.1.10",
+        "udp:0.0.0.0:2255,name=stars1",
+        "udp:0.0.0.0:2255,from=192.168.1.10,name=stars1",
+    ] {
+        assert_eq!(spec.parse::<Endpoint>().unwrap().to_string(), spec);
+    }
+}
+
+#[test]
+fn malformed_filters_are_rejected() {
+    for spec in [
+        "udp:2255,from=stars1",
+        "udp:2255,name=",
+        "udp:2255,to=192.168.1.10",
+        "udp:2255,stars1",
+    ] {
+        assert!(spec.parse::<Endpoint>().is_err(), "{:?} accepted", spec);
+    }
+}
+
+#[test]
+fn empty_filters_accept_anybody() {
+    let f = Filter::default();
+    assert!(f.accepts(&src("192.168.1.10"), STARS1));
+    assert!(f.accepts(&src("192.168.1.11"), "garbage"));
+}
+
+#[test]
+fn senders_are_matched_by_address() {
+    let f = filter("udp:2255,from=192.168.1.10");
+    assert!(f.accepts(&src("192.168.1.10"), STARS1));
+    assert!(f.accepts(&src("192.168.1.10"), STARS2));
+    assert!(!f.accepts(&src("192.168.1.11"), STARS1));
+}
+
+#[test]
+fn senders_are_matched_by_name() {
+    let f = filter("udp:2255,name=stars1");
+    assert!(f.accepts(&src("192.168.1.10"), STARS1));
+    assert!(f.accepts(&src("192.168.1.11"), STARS1));
+    assert!(!f.accepts(&src("192.168.1.10"), STARS2));
+    // Lines carrying no name never match
+    assert!(!f.accepts(
+        &src("192.168.1.10"),
+        "<fH 04606><tA +2987><tO +2481><mZ -0000>"
+    ));
+}
+
+#[test]
+fn both_address_and_name_must_match() {
+    let f = filter("udp:2255,from=192.168.1.10,name=stars1");
+    assert!(f.accepts(&src("192.168.1.10"), STARS1));
+    assert!(!f.accepts(&src("192.168.1.11"), STARS1));
+    assert!(!f.accepts(&src("192.168.1.10"), STARS2));
+}
+
+#[test]
+fn names_are_taken_from_json_datagrams() {
+    assert_eq!(photometer_name(STARS2).as_deref(), Some("stars2"));
+    assert_eq!(photometer_name(r#"{"udp":1}"#), None);
+    assert_eq!(photometer_name("not json"), None);
+    // Truncated datagrams still carry the name
+    assert_eq!(photometer_name(&STARS2[..40]).as_deref(), Some("stars2"));
+    assert_eq!(photometer_name(&STARS2[..20]), None);
+}
+
+// Four channels with every field a TESS4C sends, longer than 256 bytes
+fn tess4c_datagram() -> String {
+    let mut fields = vec![
+        "\"udp\":123456".to_string(),
+        "\"rev\":1".to_string(),
+        "\"name\":\"stars1234\"".to_string(),
+    ];
+    for ch in 1..=4 {
+        fields.push(format!("\"freq{}\":12345.678", ch));
+        fields.push(format!("\"mag{}\":12.345", ch));
+        fields.push(format!("\"ZP{}\":20.123", ch));
+    }
+    fields.push("\"tamb\":-12.34,\"tsky\":-34.56,\"wdBm\":-75,\"ain\":1234".to_string());
+    format!("{{{}}}", fields.join(","))
+}
+
+#[tokio::test]
+async fn whole_tess4c_datagrams_are_received() {
+    let datagram = tess4c_datagram();
+    assert!(datagram.len() > 256, "{} bytes only", datagram.len());
+    let mut transport = Transport::new("127.0.0.1", 22557, &Filter::default())
+        .await
+        .unwrap();
+    let sender = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    sender
+        .send_to(datagram.as_bytes(), "127.0.0.1:22557")
+        .await
+        .unwrap();
+    let RawSample(_, line) = transport.reading().await.unwrap();
+    assert_eq!(line, datagram);
+    let payloads = Decoder::new().decode(Utc::now(), &line).unwrap();
+    assert_eq!(payloads.len(), 4);
+}
+
+#[tokio::test]
+async fn datagrams_come_with_their_source() {
+    let filter = filter("udp:127.0.0.1:22558,name=stars1");
+    let mut transport = Transport::new("127.0.0.1", 22558, &filter).await.unwrap();
+    let stars1 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let stars2 = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    stars2
+        .send_to(STARS2.as_bytes(), "127.0.0.1:22558")
+        .await
+        .unwrap();
+    stars1
+        .send_to(STARS1.as_bytes(), "127.0.0.1:22558")
+        .await
+        .unwrap();
+    let (src, RawSample(_, line)) = transport.reading_from().await.unwrap();
+    assert_eq!(src, stars1.local_addr().unwrap());
+    assert_eq!(line, STARS1);
+}